@@ -0,0 +1,399 @@
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use challenge::{
+    format::{Columns, Format},
+    Buckets, Distribution, Options, ReadMode, Validation,
+};
+
+const DEFAULT_MEASUREMENT_FILE_PATH: &str = "measurements.txt";
+
+const USAGE: &str = "\
+Usage: challenge [OPTIONS] [INPUT]...
+
+Arguments:
+  [INPUT]...  Measurement files, directories (searched recursively) or glob patterns to
+              process, or `-` for stdin [default: stdin if it's piped, otherwise
+              measurements.txt]
+
+Options:
+  -t, --threads <N>        Number of worker threads [default: number of CPUs]
+  -o, --output <FILE>      Write the results to FILE instead of stdout
+  -f, --format <FORMAT>    Output format, `reference` (the challenge's
+                           `{Station=min/mean/max, ...}`), `json`, `csv`, `tsv`, or
+                           with the `arrow` feature, `arrow` (an Arrow IPC file) or
+                           `parquet` [default: reference]
+  -d, --delimiter <CHAR>   Field delimiter for `csv` and `tsv` output, e.g. `;`
+                           (`\\t` for a tab) [default: `,` for csv, a tab for tsv]
+  -c, --chunk-size <SIZE>  Size of the file chunks that worker threads take from a
+                           shared queue, in bytes (accepts K/M/G suffixes) [default: 32M]
+      --mmap               Map the input file into memory instead of reading it into
+                           buffers (falls back to reading if it can't be mapped)
+      --strict             Check every line against the challenge's grammar, and fail
+                           on the first malformed line (same as `--on-error fail`)
+      --on-error <ACTION>  Check every line against the challenge's grammar, and
+                           either `fail` on the first malformed line, or `skip`
+                           malformed lines and report how many were skipped
+      --per-file           Also print the results of each input file
+      --totals             Also print each station's count and sum, and the total of
+                           every station
+      --stddev             Also print each station's standard deviation and variance
+      --percentiles <LIST> Also print each station's exact percentiles, e.g. `50,90,99`,
+                           from a histogram of its measurements
+      --sketch             Estimate `--percentiles` to within 1% from a DDSketch of each
+                           station's measurements instead of a histogram, so that
+                           measurements can have any number of whole digits
+      --histogram <WIDTH>  Also print how many of each station's measurements are in
+                           each bucket of WIDTH degrees, e.g. `5` or `2.5`, in `json`
+                           and `csv` or `tsv` output
+      --histogram-edges <LIST>
+                           Like `--histogram`, but with buckets between each of the
+                           ascending edges in LIST, e.g. `-10,0,10,20`
+  -h, --help               Print this help message
+";
+
+/// Command line arguments for the binary.
+pub struct Args {
+    /// The measurement files, directories or glob patterns to process. `-` means stdin,
+    /// and is only allowed on its own.
+    pub inputs: Vec<PathBuf>,
+    /// The number of worker threads to process chunks on.
+    pub threads: NonZeroUsize,
+    /// Where to write the results. `None` means stdout.
+    pub output: Option<PathBuf>,
+    /// The format to write the results in.
+    pub format: Format,
+    /// What to write for each station.
+    pub columns: Columns,
+    /// How to keep track of each station's measurements, for [`Columns::percentiles`]
+    /// and [`Columns::histogram`].
+    pub distribution: Distribution,
+    /// The size of each file chunk.
+    pub chunk_size: NonZeroUsize,
+    /// How much checking to do on each line of the input.
+    pub validation: Validation,
+    /// How to read the input file.
+    pub read_mode: ReadMode,
+    /// Whether to print the results of each input file, as well as the total.
+    pub per_file: bool,
+}
+
+/// The reasons argument parsing can stop without producing [`Args`].
+pub enum ParseError {
+    /// `--help` was requested.
+    Help,
+    /// The arguments were invalid.
+    Invalid(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Help => f.write_str(USAGE),
+            ParseError::Invalid(message) => write!(f, "error: {message}\n\n{USAGE}"),
+        }
+    }
+}
+
+impl Args {
+    /// Parses the arguments from the process' command line.
+    pub fn from_env() -> Result<Args, ParseError> {
+        Args::parse(std::env::args().skip(1))
+    }
+
+    /// Parses `args`, which should not include the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, ParseError> {
+        let mut inputs = Vec::new();
+        let mut threads = None;
+        let mut output = None;
+        let mut format = Format::Reference;
+        let mut delimiter = None;
+        let mut columns = Columns::default();
+        let mut sketch = false;
+        let mut chunk_size = None;
+        let mut validation = Validation::Fast;
+        let mut read_mode = ReadMode::Buffered;
+        let mut per_file = false;
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Support both `--flag value` and `--flag=value`.
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg.as_str(), None),
+            };
+
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ParseError::Invalid(format!("missing value for `{flag}`")))
+            };
+
+            match flag {
+                "-h" | "--help" => return Err(ParseError::Help),
+                "-t" | "--threads" => threads = Some(parse_value(flag, &value()?)?),
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                "-f" | "--format" => format = parse_format(flag, &value()?)?,
+                "-d" | "--delimiter" => delimiter = Some(parse_delimiter(flag, &value()?)?),
+                "-c" | "--chunk-size" => chunk_size = Some(parse_size(flag, &value()?)?),
+                "--mmap" if inline_value.is_none() => read_mode = ReadMode::Mmap,
+                "--strict" if inline_value.is_none() => validation = Validation::Strict,
+                "--per-file" if inline_value.is_none() => per_file = true,
+                "--totals" if inline_value.is_none() => columns.totals = true,
+                "--stddev" if inline_value.is_none() => columns.std_dev = true,
+                "--sketch" if inline_value.is_none() => sketch = true,
+                "--histogram" => {
+                    let value = value()?;
+                    let width = u32::try_from(parse_tenths(flag, &value)?)
+                        .ok()
+                        .and_then(NonZeroU32::new)
+                        .ok_or_else(|| {
+                            ParseError::Invalid(format!("invalid value `{value}` for `{flag}`"))
+                        })?;
+
+                    columns.histogram = Some(Buckets::Width(width));
+                }
+                "--histogram-edges" => {
+                    let edges = value()?
+                        .split(',')
+                        .map(|edge| parse_tenths(flag, edge))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if edges.len() < 2 || edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+                        return Err(ParseError::Invalid(format!(
+                            "`{flag}` needs at least two edges, in ascending order"
+                        )));
+                    }
+
+                    columns.histogram = Some(Buckets::Edges(edges));
+                }
+                "--percentiles" => {
+                    columns.percentiles = value()?
+                        .split(',')
+                        .map(|percentile| parse_value(flag, percentile))
+                        .collect::<Result<_, _>>()?;
+                }
+                "--on-error" => {
+                    validation = match value()?.as_str() {
+                        "fail" => Validation::Strict,
+                        "skip" => Validation::Skip,
+                        value => {
+                            return Err(ParseError::Invalid(format!(
+                                "invalid value `{value}` for `{flag}`, expected `fail` or `skip`"
+                            )))
+                        }
+                    }
+                }
+                _ if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(ParseError::Invalid(format!("unknown option `{arg}`")))
+                }
+                _ => {
+                    if is_glob(&arg) {
+                        glob::Pattern::new(&arg).map_err(|err| {
+                            ParseError::Invalid(format!("invalid pattern `{arg}`: {err}"))
+                        })?;
+                    }
+                    inputs.push(PathBuf::from(arg));
+                }
+            }
+        }
+
+        if inputs.len() > 1 && inputs.iter().any(|input| input.as_os_str() == "-") {
+            return Err(ParseError::Invalid(
+                "`-` can't be combined with other inputs".to_owned(),
+            ));
+        }
+
+        if let Some(delimiter) = delimiter {
+            match &mut format {
+                Format::Csv {
+                    delimiter: format_delimiter,
+                } => *format_delimiter = delimiter,
+                _ => {
+                    return Err(ParseError::Invalid(
+                        "`--delimiter` can only be used with `--format csv` or `tsv`".to_owned(),
+                    ))
+                }
+            }
+        }
+
+        if columns.histogram.is_some() && !matches!(format, Format::Json | Format::Csv { .. }) {
+            return Err(ParseError::Invalid(
+                "`--histogram` can only be used with `--format json`, `csv` or `tsv`".to_owned(),
+            ));
+        }
+
+        let distribution = if sketch {
+            if columns.percentiles.is_empty() {
+                return Err(ParseError::Invalid(
+                    "`--sketch` can only be used with `--percentiles`".to_owned(),
+                ));
+            }
+
+            // Bucket counts need every measurement.
+            if columns.histogram.is_some() {
+                return Err(ParseError::Invalid(
+                    "`--sketch` can't be combined with `--histogram`".to_owned(),
+                ));
+            }
+
+            Distribution::Sketch
+        } else if !columns.percentiles.is_empty() || columns.histogram.is_some() {
+            Distribution::Histogram
+        } else {
+            Distribution::None
+        };
+
+        if inputs.is_empty() {
+            inputs.push(PathBuf::from(if stdin_is_piped() {
+                "-"
+            } else {
+                DEFAULT_MEASUREMENT_FILE_PATH
+            }));
+        }
+
+        Ok(Args {
+            inputs,
+            threads: threads.unwrap_or_else(|| Options::default().threads),
+            output,
+            format,
+            columns,
+            distribution,
+            chunk_size: chunk_size.unwrap_or(Options::DEFAULT_CHUNK_SIZE),
+            validation,
+            read_mode,
+            per_file,
+        })
+    }
+}
+
+/// Whether stdin is a pipe or a socket, rather than a terminal, a regular file or a
+/// device like `/dev/null`, in which case it's read when there are no inputs.
+fn stdin_is_piped() -> bool {
+    let stdin = io::stdin();
+
+    if stdin.is_terminal() {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::{
+            fs::File,
+            os::{fd::AsFd, unix::fs::FileTypeExt},
+        };
+
+        stdin
+            .as_fd()
+            .try_clone_to_owned()
+            .and_then(|fd| File::from(fd).metadata())
+            .is_ok_and(|metadata| {
+                let file_type = metadata.file_type();
+                file_type.is_fifo() || file_type.is_socket()
+            })
+    }
+
+    #[cfg(not(unix))]
+    true
+}
+
+/// Whether `input` should be expanded as a glob pattern, rather than used as a path.
+pub fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, ParseError> {
+    value
+        .parse()
+        .map_err(|_| ParseError::Invalid(format!("invalid value `{value}` for `{flag}`")))
+}
+
+fn parse_format(flag: &str, value: &str) -> Result<Format, ParseError> {
+    match value {
+        "reference" => Ok(Format::Reference),
+        "json" => Ok(Format::Json),
+        "csv" => Ok(Format::Csv { delimiter: b',' }),
+        "tsv" => Ok(Format::Csv { delimiter: b'\t' }),
+        #[cfg(feature = "arrow")]
+        "arrow" => Ok(Format::Arrow),
+        #[cfg(feature = "arrow")]
+        "parquet" => Ok(Format::Parquet),
+        #[cfg(not(feature = "arrow"))]
+        "arrow" | "parquet" => Err(ParseError::Invalid(format!(
+            "`{flag} {value}` requires building with `--features arrow`"
+        ))),
+        _ => Err(ParseError::Invalid(format!(
+            "invalid value `{value}` for `{flag}`, expected `reference`, `json`, `csv`, \
+             `tsv`, `arrow` or `parquet`"
+        ))),
+    }
+}
+
+/// Parses a number of degrees with at most one decimal place, e.g. `-2.5`, as tenths.
+fn parse_tenths(flag: &str, value: &str) -> Result<i32, ParseError> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+
+    let valid = (1..=4).contains(&whole.len())
+        && fraction.len() == 1
+        && whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|byte| byte.is_ascii_digit());
+
+    if !valid {
+        return Err(ParseError::Invalid(format!(
+            "invalid value `{value}` for `{flag}`"
+        )));
+    }
+
+    // At most 4 digits, so this can't overflow.
+    let tenths: i32 = format!("{whole}{fraction}").parse().unwrap();
+
+    Ok(if value.starts_with('-') {
+        -tenths
+    } else {
+        tenths
+    })
+}
+
+/// Parses a CSV delimiter, which must be a single ASCII character that can't be
+/// confused with quoting, line endings or the numbers in a row. `\t` is accepted for a
+/// tab.
+fn parse_delimiter(flag: &str, value: &str) -> Result<u8, ParseError> {
+    match value.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        &[delimiter]
+            if delimiter.is_ascii()
+                && !delimiter.is_ascii_digit()
+                && !b"\"\r\n-.".contains(&delimiter) =>
+        {
+            Ok(delimiter)
+        }
+        _ => Err(ParseError::Invalid(format!(
+            "invalid value `{value}` for `{flag}`, expected a single ASCII character other \
+             than a digit, `-`, `.`, a quote or a line ending"
+        ))),
+    }
+}
+
+/// Parses a byte count with an optional binary `K`, `M` or `G` suffix, e.g. `64M`.
+fn parse_size(flag: &str, value: &str) -> Result<NonZeroUsize, ParseError> {
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 1 << 20),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    parse_value::<NonZeroUsize>(flag, digits)?
+        .checked_mul(NonZeroUsize::new(multiplier).unwrap())
+        .ok_or_else(|| ParseError::Invalid(format!("value `{value}` for `{flag}` is too large")))
+}
@@ -0,0 +1,342 @@
+//! Decompressing gzip and zstd input, which is detected by its magic bytes.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use flate2::bufread::MultiGzDecoder;
+
+/// The magic bytes at the start of a gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// The flag of a gzip member with an extra field.
+const GZIP_FEXTRA: u8 = 0x04;
+
+/// The ID of the extra subfield of a BGZF block, which holds the block's size.
+const BGZF_SUBFIELD_ID: [u8; 2] = *b"BC";
+
+/// The magic bytes at the start of a zstd frame.
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+
+/// The magic bytes at the start of a skippable zstd frame, ignoring the low 4 bits.
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+/// The number of bytes needed to detect a compression format.
+pub(crate) const MAGIC_LEN: usize = 4;
+
+/// A compression format that we can decompress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression format from the first (up to [`MAGIC_LEN`]) bytes of the
+    /// input. Returns `None` if the input isn't compressed.
+    pub(crate) fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&ZSTD_MAGIC.to_le_bytes()) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the first (up to [`MAGIC_LEN`]) bytes of `reader` into `magic`, returning how
+/// many were read.
+pub(crate) fn read_magic(reader: &mut impl Read, magic: &mut [u8; MAGIC_LEN]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < MAGIC_LEN {
+        match reader.read(&mut magic[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+
+    Ok(len)
+}
+
+/// Decompresses `input` on the current thread. Concatenated gzip members and zstd frames
+/// are decompressed one after the other.
+pub(crate) fn decoder<'a>(
+    compression: Compression,
+    input: impl Read + 'a,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(input))),
+        Compression::Zstd => Box::new(zstd::Decoder::new(input)?),
+    })
+}
+
+/// Returns the `(start, end)` offsets of each zstd frame in `file`, skipping skippable
+/// frames, by walking the frame and block headers without decompressing anything.
+pub(crate) fn zstd_frames(file: &File) -> io::Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut frames = Vec::new();
+    let mut position = 0;
+
+    // `fill_buf` only returns an empty buffer at the end of the file.
+    while !reader.fill_buf()?.is_empty() {
+        let start = position;
+
+        let magic = read_u32(&mut reader)?;
+        position += 4;
+
+        if magic & !0xF == ZSTD_SKIPPABLE_MAGIC {
+            let len = read_u32(&mut reader)? as u64;
+            reader.seek_relative(len as i64)?;
+            position += 4 + len;
+            continue;
+        }
+
+        if magic != ZSTD_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid zstd frame at byte {start}"),
+            ));
+        }
+
+        let descriptor = read_u8(&mut reader)?;
+        position += 1;
+
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+
+        let window_descriptor_len = if single_segment { 0 } else { 1 };
+        let dictionary_id_len = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let content_size_len = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+
+        let header_len = window_descriptor_len + dictionary_id_len + content_size_len;
+        reader.seek_relative(header_len)?;
+        position += header_len as u64;
+
+        loop {
+            let mut header = [0; 3];
+            reader.read_exact(&mut header)?;
+            position += 3;
+
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+
+            let last_block = header & 1 != 0;
+            let size = (header >> 3) as u64;
+
+            let content_len = match (header >> 1) & 0x3 {
+                // Raw and compressed blocks.
+                0 | 2 => size,
+                // RLE blocks store a single byte, which is repeated `size` times.
+                1 => 1,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid zstd block at byte {}", position - 3),
+                    ))
+                }
+            };
+
+            reader.seek_relative(content_len as i64)?;
+            position += content_len;
+
+            if last_block {
+                break;
+            }
+        }
+
+        if has_checksum {
+            reader.seek_relative(4)?;
+            position += 4;
+        }
+
+        frames.push((start, position));
+    }
+
+    Ok(frames)
+}
+
+/// Returns the `(start, end)` offsets of each gzip member in `file`, if it's in the BGZF
+/// format written by `bgzip`, whose members record their own size in an extra field.
+/// Returns `None` otherwise, since the end of a plain gzip member can only be found by
+/// decompressing it.
+pub(crate) fn bgzf_members(file: &File) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut members = Vec::new();
+    let mut position = 0;
+
+    while !reader.fill_buf()?.is_empty() {
+        // ID1, ID2, CM, FLG, MTIME (4 bytes), XFL, OS and XLEN (2 bytes).
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+
+        if header[..2] != GZIP_MAGIC || header[3] & GZIP_FEXTRA == 0 {
+            return Ok(None);
+        }
+
+        let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+        reader.read_exact(&mut extra)?;
+
+        let Some((size, rest)) = bgzf_block_size(&extra).and_then(|size| {
+            let rest = size.checked_sub((header.len() + extra.len()) as u64)?;
+            Some((size, rest))
+        }) else {
+            return Ok(None);
+        };
+
+        reader.seek_relative(rest as i64)?;
+        members.push((position, position + size));
+        position += size;
+    }
+
+    Ok(Some(members))
+}
+
+/// Returns the total size of a BGZF block from the subfields of its gzip extra field.
+fn bgzf_block_size(mut extra: &[u8]) -> Option<u64> {
+    // Each subfield is a 2 byte ID, a 2 byte length, and then its data.
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+
+        if extra[..2] == BGZF_SUBFIELD_ID && len == 2 {
+            // The subfield holds the block's size minus one.
+            return Some(u16::from_le_bytes([data[0], data[1]]) as u64 + 1);
+        }
+
+        extra = &extra[4 + len..];
+    }
+
+    None
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Decompresses the zstd frames or BGZF gzip members of a file on worker threads, and
+/// reads the decompressed frames back in order.
+///
+/// Each worker takes the next frame and decompresses it fully into memory, so this is
+/// only a good idea for files with many small frames, as written by e.g. `pzstd` or
+/// `bgzip`. The workers run ahead of the reader by at most a frame per worker, plus a
+/// frame per worker that's waiting to be read.
+pub(crate) struct ParallelDecoder {
+    /// A receiver for each frame, in order, which gets the frame once it's been
+    /// decompressed. Dropping it stops the workers once they've finished their frames.
+    frames: Receiver<Receiver<io::Result<Vec<u8>>>>,
+    /// The decompressed frame being read.
+    frame: io::Cursor<Vec<u8>>,
+}
+
+impl ParallelDecoder {
+    /// Starts `threads` threads decompressing `frames` of the file at `path`.
+    pub(crate) fn new(
+        path: &Path,
+        compression: Compression,
+        frames: Vec<(u64, u64)>,
+        threads: usize,
+    ) -> Self {
+        let path: Arc<Path> = path.into();
+        let frames = Arc::new(Mutex::new(frames.into_iter()));
+
+        let (sender, receiver) = mpsc::sync_channel(threads);
+
+        for _ in 0..threads {
+            let (path, frames, sender) = (path.clone(), frames.clone(), sender.clone());
+
+            thread::spawn(move || loop {
+                let ((start, end), frame_sender) = {
+                    let mut frames = frames.lock().unwrap();
+
+                    let Some(frame) = frames.next() else {
+                        break;
+                    };
+
+                    // Queueing the frame's receiver while we still hold the lock keeps
+                    // the receivers in the same order as the frames.
+                    let (frame_sender, frame_receiver) = mpsc::sync_channel(1);
+
+                    if sender.send(frame_receiver).is_err() {
+                        // The decoder was dropped.
+                        break;
+                    }
+
+                    (frame, frame_sender)
+                };
+
+                let _ = frame_sender.send(decompress_frame(&path, compression, start, end));
+            });
+        }
+
+        ParallelDecoder {
+            frames: receiver,
+            frame: io::Cursor::default(),
+        }
+    }
+}
+
+impl Read for ParallelDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.frame.read(buf)?;
+
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            // Every worker has stopped, and so every frame has been read, once the
+            // channel is closed.
+            let Ok(frame) = self.frames.recv() else {
+                return Ok(0);
+            };
+
+            let frame = frame
+                .recv()
+                .map_err(|_| io::Error::other("decompression thread panicked"))??;
+
+            self.frame = io::Cursor::new(frame);
+        }
+    }
+}
+
+/// Decompresses the frame at `[start, end)` in the file at `path`.
+fn decompress_frame(
+    path: &Path,
+    compression: Compression,
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut frame = Vec::new();
+    decoder(compression, file.take(end - start))?.read_to_end(&mut frame)?;
+
+    Ok(frame)
+}
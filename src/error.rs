@@ -0,0 +1,93 @@
+use std::{any::Any, fmt, io, path::PathBuf};
+
+/// The ways aggregating measurements can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error occurred while reading the input (or writing the output).
+    Io {
+        /// The file being read, if the input is a file.
+        path: Option<PathBuf>,
+        /// The byte offset in the input at which the error occurred, if known.
+        offset: Option<u64>,
+        source: io::Error,
+    },
+    /// A line of the input isn't of the form `<station name>;<measurement>`.
+    MalformedLine {
+        /// The file being read, if the input is a file.
+        path: Option<PathBuf>,
+        /// The byte offset of the start of the line.
+        offset: u64,
+        /// The 1-based line number.
+        line: u64,
+    },
+    /// A worker thread panicked.
+    ThreadPanic {
+        /// The panic message, if it was a string.
+        message: Option<String>,
+    },
+}
+
+impl Error {
+    /// Returns a function that wraps an [`io::Error`] in [`Error::Io`], for use with
+    /// `map_err`.
+    pub fn io(path: Option<PathBuf>, offset: Option<u64>) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::Io {
+            path,
+            offset,
+            source,
+        }
+    }
+
+    pub(crate) fn thread_panic(payload: Box<dyn Any + Send>) -> Error {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => Some(*message),
+            Err(payload) => payload.downcast_ref::<&str>().map(|s| s.to_string()),
+        };
+
+        Error::ThreadPanic { message }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io {
+                path,
+                offset,
+                source,
+            } => {
+                f.write_str("I/O error")?;
+                if let Some(path) = path {
+                    write!(f, " in `{}`", path.display())?;
+                }
+                if let Some(offset) = offset {
+                    write!(f, " at byte {offset}")?;
+                }
+                write!(f, ": {source}")
+            }
+            Error::MalformedLine { path, offset, line } => {
+                f.write_str("malformed line")?;
+                if let Some(path) = path {
+                    write!(f, " in `{}`", path.display())?;
+                }
+                write!(f, " at line {line} (byte {offset})")
+            }
+            Error::ThreadPanic { message } => {
+                f.write_str("worker thread panicked")?;
+                if let Some(message) = message {
+                    write!(f, ": {message}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
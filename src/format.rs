@@ -0,0 +1,550 @@
+//! Output formatting for aggregated station measurements.
+
+use std::{fmt, io};
+
+use crate::{Buckets, FilesStats, Percentile, StationStats, Stats};
+
+#[cfg(feature = "arrow")]
+mod arrow;
+
+#[cfg(feature = "arrow")]
+pub use arrow::{write_arrow, write_arrow_files, write_parquet, write_parquet_files};
+
+/// The formats that results can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The format of the challenge's reference implementation, see [`write_reference`].
+    #[default]
+    Reference,
+    /// JSON, see [`write_json`].
+    Json,
+    /// Delimiter-separated values with a header row, e.g. CSV or TSV, see [`write_csv`].
+    Csv {
+        /// The byte between fields, e.g. `b','` or `b'\t'`.
+        delimiter: u8,
+    },
+    /// An Arrow IPC file, see [`write_arrow`].
+    #[cfg(feature = "arrow")]
+    Arrow,
+    /// A Parquet file, see [`write_parquet`].
+    #[cfg(feature = "arrow")]
+    Parquet,
+}
+
+/// What to write for each station, on top of its min, mean and max.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Columns {
+    /// Write each station's count and sum, followed by the [`StationStats::total`] of
+    /// every station, e.g. to check that every line was counted.
+    pub totals: bool,
+    /// Write each station's [`Stats::std_dev`] and [`Stats::variance`]. They're rounded
+    /// to two decimal places in text formats.
+    pub std_dev: bool,
+    /// Write each station's measurement at each of these percentiles, see
+    /// [`Stats::percentile`]. They're missing (e.g. `null` in JSON) for stations that
+    /// don't keep track of their measurements, see
+    /// [`Options::distribution`](crate::Options::distribution).
+    pub percentiles: Vec<Percentile>,
+    /// Write the number of each station's measurements in each of these buckets, from
+    /// its [`Stats::histogram`]. They're only written in JSON and CSV, and are missing
+    /// for stations without a histogram.
+    pub histogram: Option<Buckets>,
+}
+
+impl Format {
+    /// Writes `stats` in this format.
+    pub fn write(
+        self,
+        out: &mut impl io::Write,
+        stats: &StationStats,
+        columns: &Columns,
+    ) -> io::Result<()> {
+        match self {
+            Format::Reference => write_reference(out, stats, columns),
+            Format::Json => write_json(out, stats, columns),
+            Format::Csv { delimiter } => write_csv(out, stats, delimiter, columns),
+            #[cfg(feature = "arrow")]
+            Format::Arrow => write_arrow(out, stats, columns),
+            #[cfg(feature = "arrow")]
+            Format::Parquet => write_parquet(out, stats, columns),
+        }
+    }
+
+    /// Writes the results of each file in `stats`, followed by their total, in this
+    /// format.
+    pub fn write_files(
+        self,
+        out: &mut impl io::Write,
+        stats: &FilesStats,
+        columns: &Columns,
+    ) -> io::Result<()> {
+        match self {
+            Format::Reference => write_reference_files(out, stats, columns),
+            Format::Json => write_json_files(out, stats, columns),
+            Format::Csv { delimiter } => write_csv_files(out, stats, delimiter, columns),
+            #[cfg(feature = "arrow")]
+            Format::Arrow => write_arrow_files(out, stats, columns),
+            #[cfg(feature = "arrow")]
+            Format::Parquet => write_parquet_files(out, stats, columns),
+        }
+    }
+}
+
+/// Writes `stats` in the format of the challenge's reference implementation, i.e.
+/// `{Abha=-23.0/18.0/59.2, Abidjan=-16.2/26.0/67.3, ...}`.
+///
+/// Values are rounded the same way as the reference implementation, which rounds
+/// half towards positive infinity (`-0.05` becomes `-0.0`, which is printed as `0.0`),
+/// rather than using Rust's float formatting, which rounds half to even.
+///
+/// With [`Columns::totals`], each station's count and sum are appended to its values,
+/// and the total of every station follows, e.g. `{Abha=-23.0/18.0/59.2/3/54.0}
+/// total=-23.0/18.0/59.2/3/54.0`. With [`Columns::std_dev`], its standard deviation and
+/// variance are appended after those, and then any [`Columns::percentiles`].
+pub fn write_reference(
+    out: &mut impl io::Write,
+    stats: &StationStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    out.write_all(b"{")?;
+
+    for (i, (station, stats)) in stats.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b", ")?;
+        }
+
+        out.write_all(station)?;
+        out.write_all(b"=")?;
+        write_values(out, stats, '/', columns, false)?;
+    }
+
+    out.write_all(b"}")?;
+
+    if columns.totals && !stats.is_empty() {
+        out.write_all(b" total=")?;
+        write_values(out, &stats.total(), '/', columns, false)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the results of each file in `stats` in the reference format, one file per
+/// line, prefixed by its path, followed by their total, e.g.
+/// `a.txt: {Abha=-23.0/18.0/59.2}`, then `total: {...}`.
+pub fn write_reference_files(
+    out: &mut impl io::Write,
+    stats: &FilesStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    for (path, file_stats) in &stats.files {
+        write!(out, "{}: ", path.display())?;
+        write_reference(out, file_stats, columns)?;
+        writeln!(out)?;
+    }
+
+    write!(out, "total: ")?;
+    write_reference(out, &stats.total, columns)
+}
+
+/// Writes `stats` as a JSON array with an object per station, e.g.
+/// `[{"station":"Abha","min":-23.0,"mean":18.0,"max":59.2,"count":3,"sum":54.0}]`.
+///
+/// `min`, `mean` and `max` are rounded like [`write_reference`], and `sum` is exact.
+/// Station names that aren't valid UTF-8 have invalid bytes replaced with U+FFFD.
+///
+/// With [`Columns::totals`], the array ends with the total of every station, which has
+/// a `null` station. With [`Columns::std_dev`], each object also has `stddev` and
+/// `variance`, and each of the [`Columns::percentiles`] is named like `p99.9`.
+///
+/// With [`Columns::histogram`], each object has a `histogram` like
+/// `{"edges":[-10.0,0.0,10.0],"counts":[4,7]}`, whose buckets (if they're of a fixed
+/// width) cover just the station's measurements.
+pub fn write_json(
+    out: &mut impl io::Write,
+    stats: &StationStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    out.write_all(b"[")?;
+
+    for (i, (station, stats)) in stats.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b",")?;
+        }
+
+        out.write_all(b"{\"station\":")?;
+        write_json_string(out, &String::from_utf8_lossy(station))?;
+        write_json_values(out, stats, columns)?;
+    }
+
+    if columns.totals && !stats.is_empty() {
+        out.write_all(b",{\"station\":null")?;
+        write_json_values(out, &stats.total(), columns)?;
+    }
+
+    out.write_all(b"]")
+}
+
+/// Writes the results of each file in `stats`, and their total, as a JSON object.
+///
+/// The object looks like `{"files":[{"path":"a.txt","stations":[...]}],"total":[...]}`,
+/// where each list of stations is written like [`write_json`].
+pub fn write_json_files(
+    out: &mut impl io::Write,
+    stats: &FilesStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    out.write_all(b"{\"files\":[")?;
+
+    for (i, (path, file_stats)) in stats.files.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b",")?;
+        }
+
+        out.write_all(b"{\"path\":")?;
+        write_json_string(out, &path.to_string_lossy())?;
+        out.write_all(b",\"stations\":")?;
+        write_json(out, file_stats, columns)?;
+        out.write_all(b"}")?;
+    }
+
+    out.write_all(b"],\"total\":")?;
+    write_json(out, &stats.total, columns)?;
+    out.write_all(b"}")
+}
+
+/// Writes the values of a station as the rest of a JSON object, after its name.
+fn write_json_values(out: &mut impl io::Write, stats: &Stats, columns: &Columns) -> io::Result<()> {
+    write!(
+        out,
+        ",\"min\":{},\"mean\":{},\"max\":{},\"count\":{},\"sum\":{}",
+        Tenths(stats.min as i64),
+        Tenths(stats.rounded_mean()),
+        Tenths(stats.max as i64),
+        stats.count,
+        Tenths(stats.sum),
+    )?;
+
+    if columns.std_dev {
+        write!(
+            out,
+            ",\"stddev\":{:.2},\"variance\":{:.2}",
+            stats.std_dev(),
+            stats.variance()
+        )?;
+    }
+
+    for &percentile in &columns.percentiles {
+        match stats.percentile(percentile) {
+            Some(value) => write!(out, ",\"p{percentile}\":{}", Tenths(value as i64))?,
+            None => write!(out, ",\"p{percentile}\":null")?,
+        }
+    }
+
+    if let Some(buckets) = &columns.histogram {
+        let edges = buckets.edges(stats.min, stats.max);
+
+        match stats.histogram() {
+            Some(histogram) => {
+                let counts = histogram.bucket_counts(&edges);
+                let edges: Vec<_> = edges.iter().map(|&edge| Tenths(edge as i64)).collect();
+
+                write!(
+                    out,
+                    ",\"histogram\":{{\"edges\":[{}],\"counts\":[{}]}}",
+                    Join(&edges),
+                    Join(&counts)
+                )?;
+            }
+            None => out.write_all(b",\"histogram\":null")?,
+        }
+    }
+
+    out.write_all(b"}")
+}
+
+/// Writes `s` as a JSON string, escaping quotes, backslashes and control characters.
+fn write_json_string(out: &mut impl io::Write, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_all(c.encode_utf8(&mut [0; 4]).as_bytes())?,
+        }
+    }
+
+    out.write_all(b"\"")
+}
+
+/// Writes `stats` as delimiter-separated values, with a header row, e.g.
+/// `station,min,mean,max,count` followed by `Abha,-23.0,18.0,59.2,3`, one line per
+/// station.
+///
+/// Values are rounded like [`write_reference`]. Station names are quoted as in RFC 4180
+/// if they contain the delimiter, a quote or a line ending.
+///
+/// With [`Columns::totals`], there is also a `sum` column, and a last row with an empty
+/// station name holds the total of every station. With [`Columns::std_dev`], there are
+/// also `stddev` and `variance` columns, and each of the [`Columns::percentiles`] has a
+/// column named like `p99.9`.
+///
+/// With [`Columns::histogram`], each bucket has a column named like `-10.0..0.0`, with
+/// the number of measurements in it. Buckets of a fixed width cover the measurements of
+/// every station, so that each row has the same columns.
+pub fn write_csv(
+    out: &mut impl io::Write,
+    stats: &StationStats,
+    delimiter: u8,
+    columns: &Columns,
+) -> io::Result<()> {
+    let edges = csv_edges(stats, columns);
+
+    write_csv_header(out, &["station"], delimiter, columns, &edges)?;
+    write_csv_rows(out, None, stats, delimiter, columns, &edges)
+}
+
+/// Writes the results of each file in `stats`, and their total, like [`write_csv`], but
+/// with a `path` column first. The rows of the total have an empty path.
+pub fn write_csv_files(
+    out: &mut impl io::Write,
+    stats: &FilesStats,
+    delimiter: u8,
+    columns: &Columns,
+) -> io::Result<()> {
+    // The total covers the measurements of every file.
+    let edges = csv_edges(&stats.total, columns);
+
+    write_csv_header(out, &["path", "station"], delimiter, columns, &edges)?;
+
+    for (path, file_stats) in &stats.files {
+        write_csv_rows(
+            out,
+            Some(path.as_os_str().as_encoded_bytes()),
+            file_stats,
+            delimiter,
+            columns,
+            &edges,
+        )?;
+    }
+
+    write_csv_rows(out, Some(b""), &stats.total, delimiter, columns, &edges)
+}
+
+/// The edges of the [`Columns::histogram`] buckets, which cover the measurements of
+/// every station in `stats`.
+fn csv_edges(stats: &StationStats, columns: &Columns) -> Vec<i32> {
+    let Some(buckets) = &columns.histogram else {
+        return Vec::new();
+    };
+
+    let (min, max) = stats
+        .iter()
+        .fold((i32::MAX, i32::MIN), |(min, max), (_, stats)| {
+            (min.min(stats.min), max.max(stats.max))
+        });
+
+    buckets.edges(min, max)
+}
+
+/// Writes the header row, starting with the names of the `key` columns, and ending with
+/// a column per bucket between the histogram's `edges`.
+fn write_csv_header(
+    out: &mut impl io::Write,
+    keys: &[&str],
+    delimiter: u8,
+    columns: &Columns,
+    edges: &[i32],
+) -> io::Result<()> {
+    let d = delimiter as char;
+
+    for key in keys {
+        write!(out, "{key}{d}")?;
+    }
+
+    write!(out, "min{d}mean{d}max{d}count")?;
+
+    if columns.totals {
+        write!(out, "{d}sum")?;
+    }
+
+    if columns.std_dev {
+        write!(out, "{d}stddev{d}variance")?;
+    }
+
+    for percentile in &columns.percentiles {
+        write!(out, "{d}p{percentile}")?;
+    }
+
+    for bucket in edges.windows(2) {
+        write!(
+            out,
+            "{d}{}..{}",
+            Tenths(bucket[0] as i64),
+            Tenths(bucket[1] as i64)
+        )?;
+    }
+
+    writeln!(out)
+}
+
+/// Writes a row per station in `stats`, each starting with `path` if there is one, and
+/// ending with its count in each bucket between `edges`.
+fn write_csv_rows(
+    out: &mut impl io::Write,
+    path: Option<&[u8]>,
+    stats: &StationStats,
+    delimiter: u8,
+    columns: &Columns,
+    edges: &[i32],
+) -> io::Result<()> {
+    let total = (columns.totals && !stats.is_empty()).then(|| stats.total());
+
+    let rows = stats
+        .iter()
+        .chain(total.as_ref().map(|total| (&b""[..], total)));
+
+    for (station, stats) in rows {
+        if let Some(path) = path {
+            write_csv_field(out, path, delimiter)?;
+            out.write_all(&[delimiter])?;
+        }
+
+        write_csv_field(out, station, delimiter)?;
+        out.write_all(&[delimiter])?;
+        // The count is always written, since it's useful on its own.
+        write_values(out, stats, delimiter as char, columns, true)?;
+
+        if columns.histogram.is_some() {
+            match stats.histogram() {
+                Some(histogram) => {
+                    for count in histogram.bucket_counts(edges) {
+                        write!(out, "{}{count}", delimiter as char)?;
+                    }
+                }
+                None => {
+                    for _ in edges.windows(2) {
+                        out.write_all(&[delimiter])?;
+                    }
+                }
+            }
+        }
+
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Writes `field`, quoting it if it contains the delimiter, a quote or a line ending,
+/// with any quotes doubled.
+fn write_csv_field(out: &mut impl io::Write, field: &[u8], delimiter: u8) -> io::Result<()> {
+    let needs_quotes = field
+        .iter()
+        .any(|&byte| matches!(byte, b'"' | b'\r' | b'\n') || byte == delimiter);
+
+    if !needs_quotes {
+        return out.write_all(field);
+    }
+
+    out.write_all(b"\"")?;
+
+    for (i, part) in field.split(|&byte| byte == b'"').enumerate() {
+        if i != 0 {
+            out.write_all(b"\"\"")?;
+        }
+
+        out.write_all(part)?;
+    }
+
+    out.write_all(b"\"")
+}
+
+/// Writes a station's min, mean and max, then the values of any other `columns`,
+/// separated by `separator`. The count is also written if `count` is set.
+fn write_values(
+    out: &mut impl io::Write,
+    stats: &Stats,
+    separator: char,
+    columns: &Columns,
+    count: bool,
+) -> io::Result<()> {
+    let s = separator;
+
+    write!(
+        out,
+        "{}{s}{}{s}{}",
+        Tenths(stats.min as i64),
+        Tenths(stats.rounded_mean()),
+        Tenths(stats.max as i64)
+    )?;
+
+    if columns.totals || count {
+        write!(out, "{s}{}", stats.count)?;
+    }
+
+    if columns.totals {
+        write!(out, "{s}{}", Tenths(stats.sum))?;
+    }
+
+    if columns.std_dev {
+        write!(out, "{s}{:.2}{s}{:.2}", stats.std_dev(), stats.variance())?;
+    }
+
+    for &percentile in &columns.percentiles {
+        match stats.percentile(percentile) {
+            Some(value) => write!(out, "{s}{}", Tenths(value as i64))?,
+            None => write!(out, "{s}")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Displays a list of values separated by commas, e.g. `1,2,3`.
+struct Join<'a, T>(&'a [T]);
+
+impl<T: fmt::Display> fmt::Display for Join<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, value) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+
+            write!(f, "{value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays an integer number of tenths as a decimal with one fractional digit,
+/// e.g. `-123` as `-12.3`. Zero is always displayed as `0.0`, never `-0.0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tenths(pub i64);
+
+impl fmt::Display for Tenths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+
+        write!(f, "{sign}{}.{}", abs / 10, abs % 10)
+    }
+}
+
+impl Stats {
+    /// The mean of the station's measurements in tenths, rounded half towards positive
+    /// infinity like the reference implementation's `Math.round`.
+    pub fn rounded_mean(&self) -> i64 {
+        // round(sum / count) = floor((2 * sum + count) / (2 * count)). We use i128 so
+        // that doubling the sum can't overflow.
+        let numerator = 2 * self.sum as i128 + self.count as i128;
+        let denominator = 2 * self.count as i128;
+
+        numerator.div_euclid(denominator) as i64
+    }
+}
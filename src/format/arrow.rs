@@ -0,0 +1,219 @@
+//! Writing results as Arrow IPC and Parquet files, for loading into analytics tools.
+
+use std::{io, sync::Arc};
+
+use arrow_array::{
+    builder::{Float64Builder, StringBuilder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+
+use super::Columns;
+use crate::{FilesStats, Percentile, StationStats, Stats};
+
+/// Writes `stats` as an Arrow IPC file with a row per station.
+///
+/// The columns are `station` (utf8), `min`, `mean` and `max` (float64), `count`
+/// (uint64) and `sum` (float64), followed by `stddev` and `variance` (float64) with
+/// [`Columns::std_dev`], and a float64 column for each of the [`Columns::percentiles`],
+/// named like `p99.9`. Unlike the text formats, the mean isn't rounded. With
+/// [`Columns::totals`], a last row with a null station holds the total of every
+/// station.
+pub fn write_arrow(
+    out: &mut impl io::Write,
+    stats: &StationStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    let mut rows = Rows::new(false, columns);
+    rows.push(None, stats, columns);
+
+    write_ipc(out, rows.finish()?)
+}
+
+/// Writes the results of each file in `stats`, and their total, like [`write_arrow`],
+/// but with a `path` column (utf8) first. The rows of the total have a null path.
+pub fn write_arrow_files(
+    out: &mut impl io::Write,
+    stats: &FilesStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    write_ipc(out, files_batch(stats, columns)?)
+}
+
+/// Writes `stats` as a Parquet file, with the same columns as [`write_arrow`].
+pub fn write_parquet(
+    out: &mut impl io::Write,
+    stats: &StationStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    let mut rows = Rows::new(false, columns);
+    rows.push(None, stats, columns);
+
+    write_parquet_batch(out, rows.finish()?)
+}
+
+/// Writes the results of each file in `stats`, and their total, as a Parquet file, with
+/// the same columns as [`write_arrow_files`].
+pub fn write_parquet_files(
+    out: &mut impl io::Write,
+    stats: &FilesStats,
+    columns: &Columns,
+) -> io::Result<()> {
+    write_parquet_batch(out, files_batch(stats, columns)?)
+}
+
+fn files_batch(stats: &FilesStats, columns: &Columns) -> io::Result<RecordBatch> {
+    let mut rows = Rows::new(true, columns);
+
+    for (path, file_stats) in &stats.files {
+        rows.push(Some(&path.to_string_lossy()), file_stats, columns);
+    }
+
+    rows.push(None, &stats.total, columns);
+    rows.finish()
+}
+
+fn write_ipc(out: &mut impl io::Write, batch: RecordBatch) -> io::Result<()> {
+    let mut writer = FileWriter::try_new(out, &batch.schema()).map_err(io::Error::other)?;
+
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+fn write_parquet_batch(out: &mut impl io::Write, batch: RecordBatch) -> io::Result<()> {
+    // The writer needs to own a `Send` output, and the results are small, so it's
+    // simplest to write the file to memory first.
+    let mut writer =
+        ArrowWriter::try_new(Vec::new(), batch.schema(), None).map_err(io::Error::other)?;
+
+    writer.write(&batch).map_err(io::Error::other)?;
+    out.write_all(&writer.into_inner().map_err(io::Error::other)?)
+}
+
+/// The columns of a record batch, built up a row at a time.
+struct Rows {
+    /// Only written for the results of several files.
+    paths: Option<StringBuilder>,
+    stations: StringBuilder,
+    min: Float64Builder,
+    mean: Float64Builder,
+    max: Float64Builder,
+    count: UInt64Builder,
+    sum: Float64Builder,
+    /// The standard deviations and variances, with [`Columns::std_dev`].
+    std_dev: Option<(Float64Builder, Float64Builder)>,
+    /// The measurements at each of [`Columns::percentiles`].
+    percentiles: Vec<(Percentile, Float64Builder)>,
+}
+
+impl Rows {
+    fn new(with_paths: bool, columns: &Columns) -> Self {
+        Rows {
+            paths: with_paths.then(StringBuilder::new),
+            stations: StringBuilder::new(),
+            min: Float64Builder::new(),
+            mean: Float64Builder::new(),
+            max: Float64Builder::new(),
+            count: UInt64Builder::new(),
+            sum: Float64Builder::new(),
+            std_dev: columns
+                .std_dev
+                .then(|| (Float64Builder::new(), Float64Builder::new())),
+            percentiles: columns
+                .percentiles
+                .iter()
+                .map(|&percentile| (percentile, Float64Builder::new()))
+                .collect(),
+        }
+    }
+
+    /// Adds a row per station in `stats`, and their total with [`Columns::totals`].
+    fn push(&mut self, path: Option<&str>, stats: &StationStats, columns: &Columns) {
+        for (station, stats) in stats {
+            self.push_row(path, Some(&String::from_utf8_lossy(station)), stats);
+        }
+
+        if columns.totals && !stats.is_empty() {
+            self.push_row(path, None, &stats.total());
+        }
+    }
+
+    fn push_row(&mut self, path: Option<&str>, station: Option<&str>, stats: &Stats) {
+        if let Some(paths) = &mut self.paths {
+            paths.append_option(path);
+        }
+
+        self.stations.append_option(station);
+        self.min.append_value(stats.min());
+        self.mean.append_value(stats.mean());
+        self.max.append_value(stats.max());
+        self.count.append_value(stats.count);
+        self.sum.append_value(stats.sum as f64 / 10.0);
+
+        if let Some((std_dev, variance)) = &mut self.std_dev {
+            std_dev.append_value(stats.std_dev());
+            variance.append_value(stats.variance());
+        }
+
+        for (percentile, values) in &mut self.percentiles {
+            values.append_option(
+                stats
+                    .percentile(*percentile)
+                    .map(|value| value as f64 / 10.0),
+            );
+        }
+    }
+
+    fn finish(mut self) -> io::Result<RecordBatch> {
+        let mut fields = Vec::new();
+        let mut arrays: Vec<ArrayRef> = Vec::new();
+
+        if let Some(paths) = &mut self.paths {
+            fields.push(Field::new("path", DataType::Utf8, true));
+            arrays.push(Arc::new(paths.finish()));
+        }
+
+        fields.extend([
+            Field::new("station", DataType::Utf8, true),
+            Field::new("min", DataType::Float64, false),
+            Field::new("mean", DataType::Float64, false),
+            Field::new("max", DataType::Float64, false),
+            Field::new("count", DataType::UInt64, false),
+            Field::new("sum", DataType::Float64, false),
+        ]);
+
+        arrays.extend([
+            Arc::new(self.stations.finish()) as ArrayRef,
+            Arc::new(self.min.finish()),
+            Arc::new(self.mean.finish()),
+            Arc::new(self.max.finish()),
+            Arc::new(self.count.finish()),
+            Arc::new(self.sum.finish()),
+        ]);
+
+        if let Some((std_dev, variance)) = &mut self.std_dev {
+            fields.extend([
+                Field::new("stddev", DataType::Float64, false),
+                Field::new("variance", DataType::Float64, false),
+            ]);
+
+            arrays.extend([
+                Arc::new(std_dev.finish()) as ArrayRef,
+                Arc::new(variance.finish()),
+            ]);
+        }
+
+        for (percentile, values) in &mut self.percentiles {
+            fields.push(Field::new(
+                format!("p{percentile}"),
+                DataType::Float64,
+                true,
+            ));
+            arrays.push(Arc::new(values.finish()));
+        }
+
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(io::Error::other)
+    }
+}
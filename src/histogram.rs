@@ -0,0 +1,292 @@
+//! Counting every measurement of a station, for exact percentiles.
+
+use std::{error, fmt, num::NonZeroU32, str::FromStr};
+
+/// The smallest measurement in the challenge's grammar, in tenths.
+const MIN_MEASUREMENT: i32 = -999;
+
+/// The number of distinct measurements in the challenge's grammar, `-99.9..=99.9`.
+const BUCKETS: usize = 1999;
+
+/// The number of measurements a sparse histogram holds before it becomes dense, which is
+/// when storing them individually would take more memory than counting each bucket.
+const SPARSE_LIMIT: usize = BUCKETS * size_of::<u64>() / size_of::<i16>();
+
+/// The measurements of a station, from which any percentile can be found exactly.
+///
+/// Stations with few measurements keep a list of them, and the rest keep a count of
+/// each of the 1999 possible measurements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    repr: Repr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Repr {
+    /// Every measurement, in tenths, in no particular order.
+    Sparse(Vec<i16>),
+    /// The number of each measurement, indexed by its offset from [`MIN_MEASUREMENT`].
+    Dense(Box<[u64]>),
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            repr: Repr::Sparse(Vec::new()),
+        }
+    }
+}
+
+impl Histogram {
+    /// Adds a measurement, in tenths.
+    #[inline]
+    pub(crate) fn record(&mut self, measurement: i32) {
+        match &mut self.repr {
+            Repr::Sparse(measurements) => {
+                measurements.push(measurement as i16);
+
+                if measurements.len() > SPARSE_LIMIT {
+                    self.densify();
+                }
+            }
+            Repr::Dense(counts) => counts[bucket(measurement)] += 1,
+        }
+    }
+
+    /// Adds all of the measurements of `other`.
+    pub(crate) fn merge(&mut self, other: &Histogram) {
+        match (&mut self.repr, &other.repr) {
+            (Repr::Sparse(measurements), Repr::Sparse(other)) => {
+                measurements.extend_from_slice(other);
+
+                if measurements.len() > SPARSE_LIMIT {
+                    self.densify();
+                }
+            }
+            (Repr::Sparse(_), Repr::Dense(other)) => {
+                let mut counts = other.clone();
+                self.add_to(&mut counts);
+                self.repr = Repr::Dense(counts);
+            }
+            (Repr::Dense(counts), _) => other.add_to(counts),
+        }
+    }
+
+    /// The number of measurements.
+    pub fn count(&self) -> u64 {
+        match &self.repr {
+            Repr::Sparse(measurements) => measurements.len() as u64,
+            Repr::Dense(counts) => counts.iter().sum(),
+        }
+    }
+
+    /// The measurement at `percentile`, in tenths, or `None` if there are no
+    /// measurements.
+    ///
+    /// This is the nearest-rank percentile: the smallest measurement that at least
+    /// `percentile` percent of the measurements are less than or equal to. It's always
+    /// one of the measurements, so the median of an even number of measurements is the
+    /// lower of the middle two.
+    pub fn percentile(&self, percentile: Percentile) -> Option<i32> {
+        let rank = percentile.rank(self.count())?;
+
+        match &self.repr {
+            Repr::Sparse(measurements) => {
+                let mut measurements = measurements.clone();
+                let (_, &mut nth, _) = measurements.select_nth_unstable(rank as usize - 1);
+
+                Some(nth as i32)
+            }
+            Repr::Dense(counts) => {
+                let mut seen = 0;
+
+                counts.iter().position(|&count| {
+                    seen += count;
+                    seen >= rank
+                })
+            }
+            .map(|bucket| bucket as i32 + MIN_MEASUREMENT),
+        }
+    }
+
+    /// The number of measurements in each bucket between consecutive `edges`, which are
+    /// in tenths and ascending.
+    ///
+    /// Each bucket includes its lower edge but not its upper edge, except for the last
+    /// bucket, which includes both. Measurements outside of the edges aren't counted.
+    pub fn bucket_counts(&self, edges: &[i32]) -> Vec<u64> {
+        let mut counts = vec![0; edges.len().saturating_sub(1)];
+
+        let mut add = |measurement: i32, count: u64| {
+            let i = edges.partition_point(|&edge| edge <= measurement);
+
+            if (1..edges.len()).contains(&i) {
+                counts[i - 1] += count;
+            } else if i == edges.len() && edges.len() > 1 && edges[i - 1] == measurement {
+                counts[i - 2] += count;
+            }
+        };
+
+        match &self.repr {
+            Repr::Sparse(measurements) => {
+                for &measurement in measurements {
+                    add(measurement as i32, 1);
+                }
+            }
+            Repr::Dense(dense) => {
+                for (bucket, &count) in dense.iter().enumerate() {
+                    if count != 0 {
+                        add(bucket as i32 + MIN_MEASUREMENT, count);
+                    }
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// Adds the measurements of this histogram to the dense `counts`.
+    fn add_to(&self, counts: &mut [u64]) {
+        match &self.repr {
+            Repr::Sparse(measurements) => {
+                for &measurement in measurements {
+                    counts[bucket(measurement as i32)] += 1;
+                }
+            }
+            Repr::Dense(other) => {
+                for (count, other) in counts.iter_mut().zip(other.iter()) {
+                    *count += other;
+                }
+            }
+        }
+    }
+
+    /// Switches to counting each bucket.
+    #[cold]
+    fn densify(&mut self) {
+        let mut counts = vec![0; BUCKETS].into_boxed_slice();
+        self.add_to(&mut counts);
+        self.repr = Repr::Dense(counts);
+    }
+}
+
+/// The index of the bucket of `measurement`, in tenths.
+#[inline]
+fn bucket(measurement: i32) -> usize {
+    // Measurements outside of the grammar's range only get this far with
+    // `Validation::Fast`, which trusts the input, so clamping them is good enough.
+    (measurement.clamp(MIN_MEASUREMENT, -MIN_MEASUREMENT) - MIN_MEASUREMENT) as usize
+}
+
+/// How measurements are grouped into buckets, for [`Histogram::bucket_counts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Buckets {
+    /// Buckets of the same width, in tenths, each starting at a multiple of the width.
+    Width(NonZeroU32),
+    /// Buckets between consecutive edges, in tenths, which must be ascending.
+    Edges(Vec<i32>),
+}
+
+impl Buckets {
+    /// The edges of the buckets for measurements between `min` and `max`, in tenths.
+    ///
+    /// Buckets of a fixed width only cover that range, so there are none if `min` is
+    /// greater than `max`, while edges are always the same.
+    pub fn edges(&self, min: i32, max: i32) -> Vec<i32> {
+        match self {
+            Buckets::Width(width) => {
+                let width = width.get() as i64;
+                let first = (min as i64).div_euclid(width);
+                let last = (max as i64).div_euclid(width) + 1;
+
+                (first..=last)
+                    .map(|i| (i * width).clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+                    .collect()
+            }
+            Buckets::Edges(edges) => edges.clone(),
+        }
+    }
+}
+
+/// A percentile between 0 and 100, with up to 4 decimal places, e.g. `99.9`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Percentile {
+    /// The percentile as a fraction of the measurements, in millionths, which makes it
+    /// exact.
+    millionths: u32,
+}
+
+impl Percentile {
+    /// The 1-based rank of the nearest-rank percentile of `count` measurements, or
+    /// `None` if there are none.
+    pub(crate) fn rank(self, count: u64) -> Option<u64> {
+        if count == 0 {
+            return None;
+        }
+
+        // rank = ceil(percentile / 100 * count), and at least 1.
+        let rank = (self.millionths as u128 * count as u128).div_ceil(1_000_000);
+
+        Some((rank as u64).max(1))
+    }
+}
+
+impl FromStr for Percentile {
+    type Err = ParsePercentileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+
+        let is_digits = |s: &str| s.bytes().all(|byte| byte.is_ascii_digit());
+
+        // At most `100.0000`, without a sign or a trailing `.`.
+        let valid = (1..=3).contains(&whole.len())
+            && fraction.len() <= 4
+            && fraction.is_empty() != s.contains('.')
+            && is_digits(whole)
+            && is_digits(fraction);
+
+        if !valid {
+            return Err(ParsePercentileError);
+        }
+
+        // Both parts are short enough that they can't overflow.
+        let whole: u32 = whole.parse().unwrap();
+        let fraction: u32 = format!("{fraction:0<4}").parse().unwrap();
+
+        let millionths = whole * 10_000 + fraction;
+
+        if millionths > 1_000_000 {
+            return Err(ParsePercentileError);
+        }
+
+        Ok(Percentile { millionths })
+    }
+}
+
+/// Displays the percentile without trailing zeros, e.g. `50` or `99.9`.
+impl fmt::Display for Percentile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.millionths / 10_000;
+        let fraction = self.millionths % 10_000;
+
+        if fraction == 0 {
+            write!(f, "{whole}")
+        } else {
+            let fraction = format!("{fraction:04}");
+            write!(f, "{whole}.{}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+/// The error returned when parsing a [`Percentile`] fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsePercentileError;
+
+impl fmt::Display for ParsePercentileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected a percentile between 0 and 100, with up to 4 decimal places")
+    }
+}
+
+impl error::Error for ParsePercentileError {}
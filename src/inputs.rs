@@ -0,0 +1,84 @@
+//! Expanding the inputs given on the command line into the files to process.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use challenge::Error;
+
+use crate::cli::is_glob;
+
+/// Expands `inputs` into the files to process. Directories are replaced by the files in
+/// them, recursively, and glob patterns by the paths that match them, both in sorted
+/// order. Other inputs are kept as they are, so that e.g. named pipes still work.
+pub fn expand(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        let files_before = files.len();
+
+        let message = match input.to_str() {
+            Some(pattern) if is_glob(pattern) => {
+                // The pattern was checked when the arguments were parsed.
+                for path in glob::glob(pattern).unwrap() {
+                    let path = path.map_err(|err| {
+                        Error::io(Some(err.path().to_path_buf()), None)(err.into())
+                    })?;
+
+                    push_files(&path, &mut files)?;
+                }
+
+                "no files match"
+            }
+            _ => {
+                push_files(input, &mut files)?;
+
+                "no files in directory"
+            }
+        };
+
+        // A glob or directory without any files is more likely a wrong path than an
+        // empty dataset.
+        if files.len() == files_before {
+            return Err(Error::Io {
+                path: Some(input.clone()),
+                offset: None,
+                source: io::Error::new(io::ErrorKind::NotFound, message),
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Adds `path` to `files`, or if it's a directory, every file in it.
+fn push_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let io_error = || Error::io(Some(path.to_path_buf()), None);
+
+    let mut entries = fs::read_dir(path)
+        .map_err(io_error())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error())?;
+
+    entries.sort();
+
+    for entry in entries {
+        // Skip hidden files, e.g. editor swap files.
+        let hidden = entry
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+
+        if !hidden {
+            push_files(&entry, files)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1314 @@
+#![feature(core_io_borrowed_buf)]
+#![feature(read_buf)]
+#![feature(maybe_uninit_slice)]
+
+//! A multi-threaded engine for the One Billion Row Challenge.
+//!
+//! Input is a sequence of lines of the form `<station name>;<measurement>`, and the
+//! output is the min, mean and max measurement of each station.
+//!
+//! ```no_run
+//! use challenge::{aggregate_file, Options};
+//!
+//! let stats = aggregate_file("measurements.txt", &Options::default()).unwrap();
+//!
+//! for (station, stats) in &stats {
+//!     println!("{}: {}", String::from_utf8_lossy(station), stats.mean());
+//! }
+//! ```
+
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    buffer::{BufReader, Buffer},
+    decompress::{Compression, ParallelDecoder},
+    table::StationTable,
+};
+
+pub use crate::{
+    error::Error,
+    histogram::{Buckets, Histogram, ParsePercentileError, Percentile},
+    sketch::Sketch,
+};
+
+mod buffer;
+mod decompress;
+mod error;
+pub mod format;
+mod histogram;
+mod mmap;
+mod scan;
+mod sketch;
+mod stream;
+mod table;
+
+/// Configuration for [`aggregate_file`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The number of worker threads to process chunks on.
+    pub threads: NonZeroUsize,
+    /// The size of each file chunk. Worker threads take chunks from a shared queue
+    /// until there are none left, so smaller chunks balance the work better between
+    /// threads, at the cost of more per-chunk overhead.
+    pub chunk_size: NonZeroUsize,
+    /// How much checking to do on each line of the input.
+    pub validation: Validation,
+    /// How to read the input file.
+    pub read_mode: ReadMode,
+    /// How to keep track of each station's measurements, for percentiles.
+    pub distribution: Distribution,
+}
+
+impl Options {
+    /// The default [`Options::chunk_size`], 32 MiB.
+    pub const DEFAULT_CHUNK_SIZE: NonZeroUsize = match NonZeroUsize::new(32 << 20) {
+        Some(chunk_size) => chunk_size,
+        None => unreachable!(),
+    };
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threads: NonZeroUsize::new(num_cpus::get()).unwrap_or(NonZeroUsize::MIN),
+            chunk_size: Options::DEFAULT_CHUNK_SIZE,
+            validation: Validation::default(),
+            read_mode: ReadMode::default(),
+            distribution: Distribution::default(),
+        }
+    }
+}
+
+/// How to read the input file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Each thread reads its chunks into its own buffer.
+    #[default]
+    Buffered,
+    /// Map the whole file into memory, and parse chunks directly from the mapping.
+    /// Falls back to [`ReadMode::Buffered`] if the file can't be mapped, e.g. if it's
+    /// a pipe.
+    ///
+    /// The file must not be modified while it's mapped.
+    Mmap,
+}
+
+/// How much checking to do on each line of the input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Validation {
+    /// Assume the input is well-formed. The results for malformed input are
+    /// unspecified.
+    #[default]
+    Fast,
+    /// Fail with [`Error::MalformedLine`] on the first line that doesn't match the
+    /// challenge's grammar.
+    Strict,
+    /// Skip lines that don't match the challenge's grammar, recording them in
+    /// [`StationStats::skipped_lines`].
+    Skip,
+}
+
+/// How each station keeps track of its measurements, for [`Stats::percentile`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Distribution {
+    /// Only keep the min, max, count and sums, so there are no percentiles.
+    #[default]
+    None,
+    /// Keep a [`Histogram`], for exact percentiles. This relies on measurements being
+    /// within the challenge's range, and takes up to 16 KiB per station, per thread.
+    Histogram,
+    /// Keep a [`Sketch`], for percentiles within 1% of the exact ones, whatever the range
+    /// of the measurements.
+    ///
+    /// Measurements may then have any number of whole digits, e.g. `-12345.6`, and are
+    /// parsed line by line, which is slower. Ones outside the range of `i32` tenths are
+    /// kept exactly by the sketch, but saturate in [`Stats::min`], [`Stats::max`] and
+    /// the sums.
+    Sketch,
+}
+
+/// The aggregated measurements of every station, sorted by station name.
+#[derive(Debug, Default)]
+pub struct StationStats {
+    stations: Vec<(Vec<u8>, Stats)>,
+    skipped_lines: SkippedLines,
+    /// How the stations kept track of their measurements, for the total.
+    distribution: Distribution,
+}
+
+/// The malformed lines skipped by [`Validation::Skip`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkippedLines {
+    /// The number of lines that were skipped.
+    pub count: u64,
+    /// The offsets in the input of the first (up to [`SkippedLines::SAMPLE_SIZE`])
+    /// skipped lines, in ascending order.
+    pub sample_offsets: Vec<u64>,
+}
+
+impl SkippedLines {
+    /// The maximum number of offsets kept in [`SkippedLines::sample_offsets`].
+    pub const SAMPLE_SIZE: usize = 10;
+
+    fn record(&mut self, offset: u64) {
+        self.count += 1;
+
+        // Lines are recorded in input order within a chunk, so the first offsets we
+        // see are the ones we want to keep.
+        if self.sample_offsets.len() < Self::SAMPLE_SIZE {
+            self.sample_offsets.push(offset);
+        }
+    }
+
+    fn merge(&mut self, other: SkippedLines) {
+        self.count += other.count;
+
+        self.sample_offsets.extend(other.sample_offsets);
+        self.sample_offsets.sort_unstable();
+        self.sample_offsets.truncate(Self::SAMPLE_SIZE);
+    }
+}
+
+impl StationStats {
+    /// The malformed lines that were skipped. Always empty unless using
+    /// [`Validation::Skip`].
+    pub fn skipped_lines(&self) -> &SkippedLines {
+        &self.skipped_lines
+    }
+
+    /// The number of distinct stations.
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    /// Looks up the measurements of a single station.
+    pub fn get(&self, station: &[u8]) -> Option<&Stats> {
+        self.stations
+            .binary_search_by(|(name, _)| name.as_slice().cmp(station))
+            .ok()
+            .map(|i| &self.stations[i].1)
+    }
+
+    /// The measurements of every station combined, e.g. to check that every line was
+    /// counted.
+    pub fn total(&self) -> Stats {
+        let mut total = Stats::new(self.distribution);
+
+        for (_, stats) in &self.stations {
+            total.merge(stats);
+        }
+
+        total
+    }
+
+    /// Iterates over the stations in name order.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&[u8], &Stats)> {
+        self.stations
+            .iter()
+            .map(|(station, stats)| (station.as_slice(), stats))
+    }
+}
+
+impl<'a> IntoIterator for &'a StationStats {
+    type Item = (&'a [u8], &'a Stats);
+    type IntoIter = Box<dyn ExactSizeIterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl IntoIterator for StationStats {
+    type Item = (Vec<u8>, Stats);
+    type IntoIter = std::vec::IntoIter<(Vec<u8>, Stats)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stations.into_iter()
+    }
+}
+
+/// Aggregates the measurements in the file at `path`, using multiple threads.
+///
+/// gzip and zstd files are detected by their magic bytes and decompressed
+/// transparently, and then parsed like [`aggregate_stream`]. Offsets in errors are then
+/// offsets in the decompressed data.
+pub fn aggregate_file(path: impl AsRef<Path>, options: &Options) -> Result<StationStats, Error> {
+    let mut results = aggregate_paths(&[path.as_ref()], options)?;
+
+    Ok(sort_results(results.pop().unwrap()))
+}
+
+/// The aggregated measurements of several files, as returned by [`aggregate_files`].
+#[derive(Debug, Default)]
+pub struct FilesStats {
+    /// The aggregated measurements of all of the files together.
+    ///
+    /// Its [`StationStats::skipped_lines`] only has a count, without any offsets, since
+    /// an offset doesn't say which file it's in. The offsets are in [`FilesStats::files`].
+    pub total: StationStats,
+    /// The aggregated measurements of each file, in the order they were given.
+    pub files: Vec<(PathBuf, StationStats)>,
+}
+
+/// Aggregates the measurements in all of the files at `paths`, using multiple threads.
+///
+/// The chunks of every file are processed together, so many small files are processed
+/// as quickly as one large file. Compressed files and files that can't be split up in
+/// advance, such as pipes, are streamed one at a time, like [`aggregate_file`].
+pub fn aggregate_files(paths: &[impl AsRef<Path>], options: &Options) -> Result<FilesStats, Error> {
+    let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+
+    let files: Vec<_> = aggregate_paths(&paths, options)?
+        .into_iter()
+        .map(sort_results)
+        .collect();
+
+    let mut total = ChunkProcessingResult::new(options.distribution);
+
+    for file in &files {
+        for (station, stats) in file {
+            total
+                .results
+                .get_or_insert(table::hash(station), station)
+                .merge(stats);
+        }
+
+        total.skipped_lines.count += file.skipped_lines.count;
+    }
+
+    Ok(FilesStats {
+        total: sort_results(total),
+        files: paths
+            .into_iter()
+            .map(Path::to_path_buf)
+            .zip(files)
+            .collect(),
+    })
+}
+
+/// A file that will be processed in chunks.
+///
+/// It doesn't keep the file open, since there may be more files than we're allowed to
+/// open at once. Each chunk opens the file again instead.
+struct ChunkedFile<'a> {
+    path: &'a Path,
+    /// The file mapped into memory, with [`ReadMode::Mmap`]. A mapping stays valid after
+    /// its file is closed.
+    mapping: Option<Mmap>,
+    /// The index of the file in the paths given to [`aggregate_paths`].
+    index: usize,
+}
+
+/// Aggregates the measurements in each of the files at `paths`, returning the results
+/// of each file in the same order.
+fn aggregate_paths(
+    paths: &[&Path],
+    options: &Options,
+) -> Result<Vec<ChunkProcessingResult>, Error> {
+    // We process files in chunks using multiple threads.
+    // We split each file into many chunks of roughly `chunk_size` bytes, and then move
+    // each chunk boundary forward to the start of the next line, by probing the file at
+    // the boundary, so that each chunk only contains whole lines. Each thread then
+    // takes chunks from a shared queue until there are none left, so a slow thread
+    // only holds up the others by a single chunk, and we merge all of the results of
+    // each file together.
+
+    let mut results: Vec<Option<ChunkProcessingResult>> = paths.iter().map(|_| None).collect();
+    let mut chunked_files = Vec::new();
+
+    // The chunks of every file, as `(file, start, end)`, where `file` is an index into
+    // `chunked_files`.
+    let mut chunks = Vec::new();
+
+    for (index, &file_path) in paths.iter().enumerate() {
+        let io_error = |offset| Error::io(Some(file_path.to_path_buf()), offset);
+
+        let mut file = File::open(file_path).map_err(io_error(None))?;
+
+        let metadata = file.metadata().map_err(io_error(None))?;
+
+        let mut magic = [0; decompress::MAGIC_LEN];
+        let magic_len = decompress::read_magic(&mut file, &mut magic).map_err(io_error(Some(0)))?;
+        let magic = &magic[..magic_len];
+
+        if let Some(compression) = Compression::detect(magic) {
+            results[index] = Some(aggregate_compressed(
+                file,
+                magic,
+                file_path,
+                &metadata,
+                compression,
+                options,
+            )?);
+        } else if !metadata.is_file() {
+            // Pipes and other special files can't be split up in advance, so we stream
+            // them.
+            results[index] = Some(stream::aggregate(
+                magic.chain(file),
+                Some(file_path),
+                options,
+            )?);
+        } else {
+            let len = metadata.len();
+
+            let mapping = match options.read_mode {
+                ReadMode::Mmap => mmap::map(&file, len),
+                ReadMode::Buffered => None,
+            };
+
+            let chunk_count = len.div_ceil(options.chunk_size.get() as u64).max(1);
+            let file_chunks: Vec<_> = chunk_indices(chunk_count, len).collect();
+
+            let file_chunks = match &mapping {
+                Some(mapping) => align_chunks(&file_chunks, |offset| {
+                    Ok(mmap::next_line_start(mapping, offset))
+                })?,
+                None => align_chunks(&file_chunks, |offset| {
+                    next_line_start(&mut file, offset, len).map_err(io_error(Some(offset)))
+                })?,
+            };
+
+            let i = chunked_files.len();
+            chunks.extend(file_chunks.into_iter().map(|(start, end)| (i, start, end)));
+
+            // `file` is closed here, so only the chunks being processed have files open.
+            chunked_files.push(ChunkedFile {
+                path: file_path,
+                mapping,
+                index,
+            });
+        }
+    }
+
+    let chunk_processing_results = process_chunks(
+        &chunks,
+        chunked_files.len(),
+        options.threads,
+        options.distribution,
+        |i, start, end| {
+            let file_path = chunked_files[i].path;
+
+            match &chunked_files[i].mapping {
+                Some(mapping) => mmap::process_chunk(mapping, file_path, start, end, options),
+                None => process_chunk(file_path, start, end, options),
+            }
+        },
+    );
+
+    match chunk_processing_results {
+        Ok(chunk_processing_results) => {
+            for (chunked_file, result) in chunked_files.iter().zip(chunk_processing_results) {
+                results[chunked_file.index] = Some(result);
+            }
+        }
+        // The chunks are processed in any order, so this may not be the first malformed
+        // line in the file, and its line number is only relative to its chunk.
+        Err(Error::MalformedLine {
+            path: Some(path), ..
+        }) => return Err(first_malformed_line(&path, options.distribution)),
+        Err(err) => return Err(err),
+    }
+
+    Ok(results.into_iter().map(Option::unwrap).collect())
+}
+
+/// Aggregates the measurements in a compressed file, by streaming it through a decoder.
+/// A zstd file with several frames, or a gzip file in the BGZF format, has its frames
+/// decompressed in parallel.
+fn aggregate_compressed(
+    mut file: File,
+    magic: &[u8],
+    file_path: &Path,
+    metadata: &Metadata,
+    compression: Compression,
+    options: &Options,
+) -> Result<ChunkProcessingResult, Error> {
+    let io_error = || Error::io(Some(file_path.to_path_buf()), None);
+
+    if !metadata.is_file() {
+        let decoder = decompress::decoder(compression, magic.chain(file)).map_err(io_error())?;
+
+        return stream::aggregate(decoder, Some(file_path), options);
+    }
+
+    let frames = match compression {
+        Compression::Gzip => decompress::bgzf_members(&file).map_err(io_error())?,
+        Compression::Zstd => Some(decompress::zstd_frames(&file).map_err(io_error())?),
+    };
+
+    if let Some(frames) = frames.filter(|frames| frames.len() > 1) {
+        // Decompressing takes about as long as parsing, so split the threads between
+        // them, rather than running twice as many threads as we were given.
+        let threads = options.threads.get();
+        let decompress_threads = threads.div_ceil(2);
+        let options = Options {
+            threads: NonZeroUsize::new(threads - decompress_threads).unwrap_or(NonZeroUsize::MIN),
+            ..options.clone()
+        };
+
+        let decoder = ParallelDecoder::new(file_path, compression, frames, decompress_threads);
+
+        return stream::aggregate(decoder, Some(file_path), &options);
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(io_error())?;
+
+    let decoder = decompress::decoder(compression, file).map_err(io_error())?;
+
+    stream::aggregate(decoder, Some(file_path), options)
+}
+
+/// Moves the start and end of each chunk forward to the start of the next line, as
+/// given by `next_line_start`, so that each chunk only contains whole lines.
+fn align_chunks(
+    chunks: &[(u64, u64)],
+    mut next_line_start: impl FnMut(u64) -> Result<u64, Error>,
+) -> Result<Vec<(u64, u64)>, Error> {
+    let mut start = 0;
+
+    chunks
+        .iter()
+        .map(|&(_, end)| {
+            let aligned = (start, next_line_start(end)?);
+            start = aligned.1;
+            Ok(aligned)
+        })
+        .collect()
+}
+
+/// Returns the offset of the first line start in `file` at or after `offset`, by reading
+/// a small probe of the file at a time until we find a newline.
+fn next_line_start(file: &mut File, offset: u64, file_len: u64) -> io::Result<u64> {
+    if offset == 0 || offset >= file_len {
+        return Ok(offset.min(file_len));
+    }
+
+    // Start from the byte before `offset`, in case `offset` is already a line start.
+    let mut position = offset - 1;
+    file.seek(SeekFrom::Start(position))?;
+
+    let mut probe = [0; 256];
+
+    loop {
+        let read = file.read(&mut probe)?;
+
+        if read == 0 {
+            return Ok(file_len);
+        }
+
+        if let Some(newline) = probe[..read].iter().position(|&byte| byte == b'\n') {
+            return Ok(position + newline as u64 + 1);
+        }
+
+        position += read as u64;
+    }
+}
+
+/// Processes `chunks` of `file_count` files with `process_chunk` on up to `threads`
+/// threads, which each take the next unprocessed chunk until there are none left, and
+/// merges the results of each file, whose stations keep track of their measurements
+/// according to `distribution`.
+fn process_chunks(
+    chunks: &[(usize, u64, u64)],
+    file_count: usize,
+    threads: NonZeroUsize,
+    distribution: Distribution,
+    process_chunk: impl Fn(usize, u64, u64) -> Result<ChunkProcessingResult, Error> + Sync,
+) -> Result<Vec<ChunkProcessingResult>, Error> {
+    let process_chunk = &process_chunk;
+
+    // The index of the next chunk to process.
+    let next_chunk = &AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.get().min(chunks.len()))
+            .map(|_| {
+                s.spawn(move || {
+                    let mut results: Vec<_> = (0..file_count)
+                        .map(|_| ChunkProcessingResult::new(distribution))
+                        .collect();
+
+                    while let Some(&(file, start, end)) =
+                        chunks.get(next_chunk.fetch_add(1, Ordering::Relaxed))
+                    {
+                        match process_chunk(file, start, end) {
+                            Ok(chunk_result) => {
+                                let result = mem::replace(
+                                    &mut results[file],
+                                    ChunkProcessingResult::new(distribution),
+                                );
+                                results[file] = merge_chunk_results(result, chunk_result);
+                            }
+                            Err(err) => {
+                                // Stop the other threads from taking any more chunks.
+                                next_chunk.store(chunks.len(), Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+
+                    Ok(results)
+                })
+            })
+            .collect();
+
+        let mut results: Vec<_> = (0..file_count)
+            .map(|_| ChunkProcessingResult::new(distribution))
+            .collect();
+
+        for handle in handles {
+            let thread_results = handle.join().map_err(Error::thread_panic)??;
+
+            results = results
+                .into_iter()
+                .zip(thread_results)
+                .map(|(a, b)| merge_chunk_results(a, b))
+                .collect();
+        }
+
+        Ok(results)
+    })
+}
+
+/// Aggregates the measurements read from `reader` on the current thread.
+///
+/// Only [`Options::validation`] and [`Options::distribution`] apply, the other options
+/// are ignored.
+pub fn aggregate_reader(reader: impl Read, options: &Options) -> Result<StationStats, Error> {
+    let chunk_processing_result =
+        process_reader(reader, None, 0, options.validation, options.distribution)?;
+
+    Ok(sort_results(chunk_processing_result))
+}
+
+/// Aggregates the measurements read from `reader`, using multiple threads.
+///
+/// The current thread reads the input in blocks of about [`Options::chunk_size`] bytes,
+/// and hands them to worker threads to parse, so unlike [`aggregate_file`], this works
+/// for input that can't be split up in advance, such as a pipe.
+///
+/// gzip and zstd input is detected by its magic bytes and decompressed transparently.
+///
+/// [`Options::read_mode`] is ignored.
+pub fn aggregate_stream(mut reader: impl Read, options: &Options) -> Result<StationStats, Error> {
+    let mut magic = [0; decompress::MAGIC_LEN];
+    let magic_len =
+        decompress::read_magic(&mut reader, &mut magic).map_err(Error::io(None, Some(0)))?;
+    let reader = magic[..magic_len].chain(reader);
+
+    match Compression::detect(&magic[..magic_len]) {
+        Some(compression) => {
+            let decoder =
+                decompress::decoder(compression, reader).map_err(Error::io(None, None))?;
+
+            stream::aggregate(decoder, None, options)
+        }
+        None => stream::aggregate(reader, None, options),
+    }
+    .map(sort_results)
+}
+
+/// Aggregates the measurements in `bytes` on the current thread.
+///
+/// Only [`Options::validation`] and [`Options::distribution`] apply, the other options
+/// are ignored.
+pub fn aggregate_bytes(bytes: &[u8], options: &Options) -> Result<StationStats, Error> {
+    aggregate_reader(bytes, options)
+}
+
+/// Sorts the results by station name.
+fn sort_results(chunk_processing_result: ChunkProcessingResult) -> StationStats {
+    let distribution = chunk_processing_result.results.distribution();
+
+    let mut stations = chunk_processing_result
+        .results
+        .into_stations()
+        .collect::<Vec<_>>();
+
+    stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    StationStats {
+        stations,
+        skipped_lines: chunk_processing_result.skipped_lines,
+        distribution,
+    }
+}
+
+/// Sequentially re-parses the file at `file_path` with strict validation, to find the
+/// offset and line number of its first malformed line. `distribution` only matters for
+/// which measurements are well-formed, see [`Distribution::Sketch`].
+fn first_malformed_line(file_path: &Path, distribution: Distribution) -> Error {
+    let result = File::open(file_path)
+        .map_err(Error::io(Some(file_path.to_path_buf()), None))
+        .and_then(|file| {
+            process_reader(file, Some(file_path), 0, Validation::Strict, distribution)
+        });
+
+    match result {
+        Err(err) => err,
+        // We only get here if the parallel pass found a malformed line.
+        Ok(_) => Error::Io {
+            path: Some(file_path.to_path_buf()),
+            offset: None,
+            source: io::Error::other("file changed while it was being read"),
+        },
+    }
+}
+
+struct ChunkProcessingResult {
+    /// The parsed measurement data for the measurements in the chunk.
+    results: StationTable,
+    /// The malformed lines that were skipped in the chunk.
+    skipped_lines: SkippedLines,
+}
+
+impl ChunkProcessingResult {
+    /// An empty result, to merge the results of chunks into, whose stations keep track
+    /// of their measurements according to `distribution`.
+    fn new(distribution: Distribution) -> Self {
+        ChunkProcessingResult {
+            results: StationTable::new(distribution),
+            skipped_lines: SkippedLines::default(),
+        }
+    }
+}
+
+/// Opens the file at `file_path` and parses measurements from `[chunk_start, chunk_end)`,
+/// which must only contain whole lines.
+fn process_chunk(
+    file_path: &Path,
+    chunk_start: u64,
+    chunk_end: u64,
+    options: &Options,
+) -> Result<ChunkProcessingResult, Error> {
+    let io_error = || Error::io(Some(file_path.to_path_buf()), Some(chunk_start));
+
+    let mut file = File::open(file_path).map_err(io_error())?;
+
+    if chunk_start != 0 {
+        file.seek(SeekFrom::Start(chunk_start))
+            .map_err(io_error())?;
+    }
+
+    // .take() ensures each thread doesn't read past its chunk.
+    process_reader(
+        file.take(chunk_end - chunk_start),
+        Some(file_path),
+        chunk_start,
+        options.validation,
+        options.distribution,
+    )
+}
+
+/// Parses measurements from `reader`, which must only contain whole lines, except that
+/// the last line may be missing its newline. `path` and `start_offset` describe where
+/// the reader's data comes from, for errors. Each station keeps track of its
+/// measurements according to `distribution`.
+fn process_reader(
+    reader: impl Read,
+    path: Option<&Path>,
+    start_offset: u64,
+    validation: Validation,
+    distribution: Distribution,
+) -> Result<ChunkProcessingResult, Error> {
+    let mut reader = BufReader::new(reader);
+
+    // The offset in the input of the start of `bytes`.
+    let mut position = start_offset;
+    let io_error = |offset| Error::io(path.map(Path::to_path_buf), Some(offset));
+
+    // The number of lines before `bytes`, only counted when validating, for errors.
+    let mut lines = 0;
+    let malformed_line = |offset, line| Error::MalformedLine {
+        path: path.map(Path::to_path_buf),
+        offset,
+        line,
+    };
+
+    let mut results = StationTable::new(distribution);
+
+    let mut bytes = reader.fill_buf().map_err(io_error(position))?;
+
+    // The indices in `bytes` of the lines skipped by the last parse.
+    let mut skipped = Vec::new();
+    let mut skipped_lines = SkippedLines::default();
+
+    // Parse lines from the reader. When we parse a line, we mark the
+    // input up to that point as consumed. Then, when we've exhausted the
+    // buffer, we backshift the unconsumed tail portion to the start of
+    // the buffer and refill it up to capacity.
+    while !bytes.is_empty() {
+        let consumed =
+            parse(0, bytes, &mut results, validation, &mut skipped).map_err(|index| {
+                malformed_line(
+                    position + index as u64,
+                    lines + count_lines(&bytes[..index]) + 1,
+                )
+            })?;
+
+        for index in skipped.drain(..) {
+            skipped_lines.record(position + index as u64);
+        }
+
+        if validation != Validation::Fast {
+            lines += count_lines(&bytes[..consumed]);
+        }
+
+        // Inform the reader of how many bytes we actually 'used'.
+        reader.consume(consumed);
+        position += consumed as u64;
+
+        // Shift any unconsumed bytes to the start of the buffer.
+        reader.buf.backshift();
+
+        if reader.buf.buffer().len() == reader.buf.capacity() {
+            // The line is longer than the whole buffer, so make room for more of it.
+            // Otherwise, reading more would return 0 as if the input had ended.
+            let mut larger = Buffer::with_capacity(reader.buf.capacity() * 2);
+            larger
+                .read_more(reader.buf.buffer())
+                .expect("reading from a slice can't fail");
+            reader.buf = larger;
+        }
+
+        // Fill the buffer up to capacity, or with all remaining bytes from the
+        // file.
+        let read = reader
+            .buf
+            .read_more(&mut reader.inner)
+            .map_err(io_error(position + reader.buf.buffer().len() as u64))?;
+        bytes = reader.buf.buffer();
+
+        if read == 0 {
+            break;
+        }
+    }
+
+    if !bytes.is_empty() {
+        // The input doesn't end with a newline, so the last measurement is still
+        // unconsumed.
+        let mut line = bytes.to_vec();
+        line.push(b'\n');
+
+        parse(0, &line, &mut results, validation, &mut skipped)
+            .map_err(|_| malformed_line(position, lines + 1))?;
+
+        if !skipped.is_empty() {
+            skipped_lines.record(position);
+        }
+    }
+
+    Ok(ChunkProcessingResult {
+        results,
+        skipped_lines,
+    })
+}
+
+/// Parses measurements from `chunk`, which must only contain whole lines, except that
+/// the last line may be missing its newline. `path` and `chunk_start` describe where
+/// the chunk comes from, for errors.
+fn process_bytes(
+    chunk: &[u8],
+    path: Option<&Path>,
+    chunk_start: u64,
+    validation: Validation,
+    distribution: Distribution,
+) -> Result<ChunkProcessingResult, Error> {
+    let malformed_line = |index: usize| Error::MalformedLine {
+        path: path.map(Path::to_path_buf),
+        offset: chunk_start + index as u64,
+        line: count_lines(&chunk[..index]) + 1,
+    };
+
+    let mut results = StationTable::new(distribution);
+    let mut skipped = Vec::new();
+    let mut skipped_lines = SkippedLines::default();
+
+    let consumed =
+        parse(0, chunk, &mut results, validation, &mut skipped).map_err(malformed_line)?;
+
+    for index in skipped.drain(..) {
+        skipped_lines.record(chunk_start + index as u64);
+    }
+
+    if consumed < chunk.len() {
+        // The chunk doesn't end with a newline, so the last measurement is still
+        // unconsumed.
+        let mut line = chunk[consumed..].to_vec();
+        line.push(b'\n');
+
+        parse(0, &line, &mut results, validation, &mut skipped)
+            .map_err(|_| malformed_line(consumed))?;
+
+        if !skipped.is_empty() {
+            skipped_lines.record(chunk_start + consumed as u64);
+        }
+    }
+
+    Ok(ChunkProcessingResult {
+        results,
+        skipped_lines,
+    })
+}
+
+fn count_lines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|&&byte| byte == b'\n').count() as u64
+}
+
+/// Parses measurements from `buffer` with the given validation. Returns the number of
+/// bytes that were consumed, or the index of the first malformed line. The indices of
+/// skipped lines are added to `skipped`.
+fn parse(
+    start_index: usize,
+    buffer: &[u8],
+    results: &mut StationTable,
+    validation: Validation,
+    skipped: &mut Vec<usize>,
+) -> Result<usize, usize> {
+    if results.distribution() == Distribution::Sketch {
+        return parse_buffer_wide(start_index, buffer, results, validation, skipped);
+    }
+
+    match validation {
+        Validation::Fast => Ok(parse_buffer(start_index, buffer, results)),
+        Validation::Strict | Validation::Skip => {
+            parse_buffer_validating(start_index, buffer, results, validation, skipped)
+        }
+    }
+}
+
+/// Parses measurements from `buffer`, line-by-line. Returns the number of bytes that were
+/// consumed. If the buffer ends in the middle of a measurement, then
+/// `consumed != buffer.len()`.
+fn parse_buffer(start_index: usize, buffer: &[u8], results: &mut StationTable) -> usize {
+    let mut i = start_index;
+
+    // Everything before `start_index` has already been handled by the caller.
+    let mut consumed = start_index;
+
+    loop {
+        // Skip blank lines.
+        while buffer.get(i) == Some(&b'\n') {
+            i += 1;
+            consumed = i;
+        }
+
+        let Some((station_len, hash)) = scan::find_station_end(&buffer[i..]) else {
+            break;
+        };
+
+        let station = &buffer[i..i + station_len];
+
+        let measurement_start = i + station_len + 1;
+
+        let (measurement, measurement_len) = parse_measurement(&buffer[measurement_start..]);
+
+        if measurement_start + measurement_len >= buffer.len() {
+            // The buffer ends in the middle of the measurement.
+            break;
+        }
+
+        results.get_or_insert(hash, station).record(measurement);
+
+        i = measurement_start + measurement_len + 1;
+        consumed = i;
+    }
+
+    consumed
+}
+
+/// Like [`parse_buffer`], but checks that every line matches the challenge's grammar:
+/// a station name of 1 to 100 bytes of UTF-8, then `;`, then a measurement between
+/// -99.9 and 99.9 with exactly one fractional digit. Blank lines are skipped. Returns the
+/// index of the first line that doesn't match with [`Validation::Strict`], and otherwise
+/// skips such lines, adding their indices to `skipped` with [`Validation::Skip`].
+fn parse_buffer_validating(
+    start_index: usize,
+    buffer: &[u8],
+    results: &mut StationTable,
+    validation: Validation,
+    skipped: &mut Vec<usize>,
+) -> Result<usize, usize> {
+    let mut consumed = start_index;
+
+    while let Some(len) = scan::find_byte(&buffer[consumed..], b'\n') {
+        let line = &buffer[consumed..consumed + len];
+
+        if !line.is_empty() {
+            match (parse_line_strict(line), validation) {
+                (Some((station, measurement)), _) => {
+                    record_measurement(results, station, measurement);
+                }
+                (None, Validation::Strict) => return Err(consumed),
+                (None, Validation::Skip) => skipped.push(consumed),
+                (None, Validation::Fast) => {}
+            }
+        }
+
+        consumed += len + 1;
+    }
+
+    Ok(consumed)
+}
+
+/// Like [`parse_buffer_validating`], but measurements may have any number of whole
+/// digits, for [`Distribution::Sketch`]. With [`Validation::Fast`], lines that don't
+/// match are skipped without being recorded.
+fn parse_buffer_wide(
+    start_index: usize,
+    buffer: &[u8],
+    results: &mut StationTable,
+    validation: Validation,
+    skipped: &mut Vec<usize>,
+) -> Result<usize, usize> {
+    let mut consumed = start_index;
+
+    while let Some(len) = scan::find_byte(&buffer[consumed..], b'\n') {
+        let line = &buffer[consumed..consumed + len];
+
+        if !line.is_empty() {
+            let parsed = split_line(line).and_then(|(station, measurement_bytes)| {
+                Some((station, parse_measurement_wide(measurement_bytes)?))
+            });
+
+            match (parsed, validation) {
+                (Some((station, measurement)), _) => results
+                    .get_or_insert(table::hash(station), station)
+                    .record_wide(measurement),
+                (None, Validation::Strict) => return Err(consumed),
+                (None, Validation::Skip) => skipped.push(consumed),
+                (None, Validation::Fast) => {}
+            }
+        }
+
+        consumed += len + 1;
+    }
+
+    Ok(consumed)
+}
+
+/// Splits a line (without its newline) into its station name and measurement, returning
+/// `None` if it doesn't match the challenge's grammar.
+fn parse_line_strict(line: &[u8]) -> Option<(&[u8], i32)> {
+    let (station, measurement_bytes) = split_line(line)?;
+
+    let unsigned = measurement_bytes
+        .strip_prefix(b"-")
+        .unwrap_or(measurement_bytes);
+
+    let valid = match unsigned {
+        [whole, b'.', fractional] => whole.is_ascii_digit() && fractional.is_ascii_digit(),
+        [tens, ones, b'.', fractional] => {
+            tens.is_ascii_digit() && ones.is_ascii_digit() && fractional.is_ascii_digit()
+        }
+        _ => false,
+    };
+
+    valid.then(|| (station, parse_measurement(measurement_bytes).0))
+}
+
+/// Splits a line (without its newline) at its first `;`, returning `None` if the station
+/// name before it isn't 1 to 100 bytes of UTF-8.
+fn split_line(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let separator = scan::find_byte(line, b';')?;
+
+    let station = &line[..separator];
+
+    if station.is_empty() || station.len() > 100 || std::str::from_utf8(station).is_err() {
+        return None;
+    }
+
+    Some((station, &line[separator + 1..]))
+}
+
+/// Parses a measurement with any number of whole digits and exactly one fractional
+/// digit, e.g. `-12345.6`, returning it in tenths, or `None` if it doesn't match. It's
+/// a float so that it can't overflow, which is fine for a [`Sketch`], but it's only
+/// exact up to 2^53 tenths.
+fn parse_measurement_wide(bytes: &[u8]) -> Option<f64> {
+    let (sign, unsigned) = match bytes.strip_prefix(b"-") {
+        Some(unsigned) => (-1.0, unsigned),
+        None => (1.0, bytes),
+    };
+
+    let [whole @ .., b'.', fractional] = unsigned else {
+        return None;
+    };
+
+    let digits = || whole.iter().chain([fractional]);
+
+    if whole.is_empty() || !digits().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let tenths = digits().fold(0.0, |tenths, digit| tenths * 10.0 + (digit - b'0') as f64);
+
+    // Hundreds of digits overflow to infinity, which a sketch can't bin.
+    tenths.is_finite().then_some(sign * tenths)
+}
+
+/// Records a measurement of `station`, hashing its name. The fast path hashes names
+/// while it scans them instead.
+fn record_measurement(results: &mut StationTable, station: &[u8], measurement: i32) {
+    results
+        .get_or_insert(table::hash(station), station)
+        .record(measurement);
+}
+
+/// Parses a measurement with exactly one fractional digit from the start of `bytes`,
+/// returning it as an integer number of tenths, e.g. `-12.3` -> `-123`, along with the
+/// length of the measurement, which is the index of the newline after it. If the index
+/// isn't less than `bytes.len()`, then `bytes` ends in the middle of the measurement,
+/// and the returned value is meaningless.
+///
+/// The measurement is loaded as a single little-endian word and decoded without any
+/// branches or loops, using the fact that it's one of `d.d`, `dd.d`, `-d.d` or `-dd.d`.
+#[inline]
+fn parse_measurement(bytes: &[u8]) -> (i32, usize) {
+    let word = match bytes.get(..8) {
+        Some(word) => u64::from_le_bytes(word.try_into().unwrap()),
+        None => {
+            let mut word = [0; 8];
+            word[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(word)
+        }
+    };
+
+    // Digits (0x30..=0x39) have bit 4 set, but `.` (0x2E) doesn't, so this is the bit
+    // index of bit 4 of the `.`, which is in byte 1, 2 or 3. Malformed measurements
+    // like `1234` don't have one, so clamp it to keep the shifts below in range.
+    let dot = (!word & 0x10_10_10_00).trailing_zeros().min(28);
+
+    // All ones if the first byte is a `-` (0x2D), which doesn't have bit 4 set either.
+    let sign = ((!word << 59) as i64 >> 63) as u64;
+
+    // Clear the sign, and shift the digits so that the fractional digit is always in
+    // byte 4, the ones in byte 2 and the tens (or zero) in byte 1. Keeping only the low
+    // nibble of each turns ASCII digits into their values.
+    let digits = ((word & !(sign & 0xFF)) << (28 - dot)) & 0x0F_00_0F_0F_00;
+
+    // Multiplies the tens by 100 and the ones by 10, and sums them with the fractional
+    // digit, in bits 32..42.
+    let magnitude = (digits.wrapping_mul(0x64_0a_00_01) >> 32) & 0x3FF;
+
+    // Negate the magnitude if there's a sign.
+    let measurement = (magnitude ^ sign).wrapping_sub(sign) as i64 as i32;
+
+    (measurement, dot as usize / 8 + 2)
+}
+
+/// Combines the data from two chunks into one.
+fn merge_chunk_results(
+    mut a: ChunkProcessingResult,
+    b: ChunkProcessingResult,
+) -> ChunkProcessingResult {
+    a.skipped_lines.merge(b.skipped_lines);
+    a.results.merge(b.results);
+
+    a
+}
+
+/// The aggregated measurements of a single station.
+///
+/// Measurements are stored as an exact integer number of tenths, e.g. `-12.3` is
+/// stored as `-123`, and are only converted to decimals for output.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /// The minimum measurement, in tenths.
+    pub min: i32,
+    /// The sum of all measurements, in tenths.
+    pub sum: i64,
+    /// The number of measurements.
+    pub count: u64,
+    /// The sum of the squares of all measurements, in hundredths, for the variance.
+    pub sum_of_squares: u64,
+    /// The maximum measurement, in tenths.
+    pub max: i32,
+    /// The distribution of the measurements, unless [`Options::distribution`] is
+    /// [`Distribution::None`]. It's boxed to keep the stats small when it's not.
+    summary: Option<Box<Summary>>,
+}
+
+impl Stats {
+    /// The minimum measurement.
+    pub fn min(&self) -> f64 {
+        self.min as f64 / 10.0
+    }
+
+    /// The mean of the station's measurements.
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / (10 * self.count) as f64
+    }
+
+    /// The maximum measurement.
+    pub fn max(&self) -> f64 {
+        self.max as f64 / 10.0
+    }
+
+    /// The population variance of the station's measurements.
+    pub fn variance(&self) -> f64 {
+        // variance = (count * sum_of_squares - sum^2) / count^2, which is exact up to the
+        // final division since every term is an integer.
+        let count = self.count as i128;
+        let numerator = count * self.sum_of_squares as i128 - (self.sum as i128).pow(2);
+
+        numerator as f64 / (count * count * 100) as f64
+    }
+
+    /// The population standard deviation of the station's measurements.
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The histogram of the station's measurements, with [`Distribution::Histogram`].
+    pub fn histogram(&self) -> Option<&Histogram> {
+        match self.summary.as_deref()? {
+            Summary::Histogram(histogram) => Some(histogram),
+            Summary::Sketch(_) => None,
+        }
+    }
+
+    /// The sketch of the station's measurements, with [`Distribution::Sketch`].
+    pub fn sketch(&self) -> Option<&Sketch> {
+        match self.summary.as_deref()? {
+            Summary::Sketch(sketch) => Some(sketch),
+            Summary::Histogram(_) => None,
+        }
+    }
+
+    /// The measurement at `percentile`, in tenths, unless [`Options::distribution`] is
+    /// [`Distribution::None`]. See [`Histogram::percentile`] and [`Sketch::percentile`].
+    ///
+    /// A sketch's estimate is rounded to the nearest tenth, and kept between the
+    /// minimum and maximum measurements.
+    pub fn percentile(&self, percentile: Percentile) -> Option<i32> {
+        match self.summary.as_deref()? {
+            Summary::Histogram(histogram) => histogram.percentile(percentile),
+            Summary::Sketch(sketch) => sketch
+                .percentile(percentile)
+                .map(|value| (value.round() as i32).clamp(self.min, self.max)),
+        }
+    }
+
+    /// Empty stats that keep track of their measurements according to `distribution`.
+    fn new(distribution: Distribution) -> Self {
+        let summary = match distribution {
+            Distribution::None => None,
+            Distribution::Histogram => Some(Summary::Histogram(Histogram::default())),
+            Distribution::Sketch => Some(Summary::Sketch(Sketch::default())),
+        };
+
+        Stats {
+            summary: summary.map(Box::new),
+            ..Stats::default()
+        }
+    }
+
+    /// Adds a measurement, in tenths.
+    #[inline]
+    fn record(&mut self, measurement: i32) {
+        self.sum += measurement as i64;
+        self.count += 1;
+        self.sum_of_squares += (measurement * measurement) as u64;
+
+        self.max = i32::max(measurement, self.max);
+        self.min = i32::min(measurement, self.min);
+
+        match self.summary.as_deref_mut() {
+            None => {}
+            Some(Summary::Histogram(histogram)) => histogram.record(measurement),
+            Some(Summary::Sketch(sketch)) => sketch.record(measurement as f64),
+        }
+    }
+
+    /// Adds a measurement, in tenths, that may be outside the range of `i32`, for
+    /// [`Distribution::Sketch`]. The sketch gets its exact value, but the min, max and
+    /// sums saturate.
+    fn record_wide(&mut self, measurement: f64) {
+        // `as` saturates floats that don't fit.
+        let saturated = measurement as i32;
+
+        self.sum = self.sum.saturating_add(saturated as i64);
+        self.count += 1;
+        self.sum_of_squares = self
+            .sum_of_squares
+            .saturating_add((saturated as i64).pow(2) as u64);
+
+        self.max = i32::max(saturated, self.max);
+        self.min = i32::min(saturated, self.min);
+
+        if let Some(Summary::Sketch(sketch)) = self.summary.as_deref_mut() {
+            sketch.record(measurement);
+        }
+    }
+
+    /// Adds all of the measurements of `other`.
+    fn merge(&mut self, other: &Stats) {
+        // Only measurements recorded with `record_wide` can get anywhere near
+        // saturating, and then the sums shouldn't wrap around either.
+        self.sum = self.sum.saturating_add(other.sum);
+        self.count += other.count;
+        self.sum_of_squares = self.sum_of_squares.saturating_add(other.sum_of_squares);
+
+        self.max = i32::max(other.max, self.max);
+        self.min = i32::min(other.min, self.min);
+
+        match (self.summary.as_deref_mut(), other.summary.as_deref()) {
+            (Some(Summary::Histogram(histogram)), Some(Summary::Histogram(other))) => {
+                histogram.merge(other);
+            }
+            (Some(Summary::Sketch(sketch)), Some(Summary::Sketch(other))) => sketch.merge(other),
+            _ => {}
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            min: i32::MAX,
+            sum: 0,
+            count: 0,
+            sum_of_squares: 0,
+            max: i32::MIN,
+            summary: None,
+        }
+    }
+}
+
+/// The distribution of a station's measurements, see [`Distribution`].
+#[derive(Clone, Debug)]
+enum Summary {
+    Histogram(Histogram),
+    Sketch(Sketch),
+}
+
+/// Splits `total_len` evenly into `num_chunks` chunks. If `num_chunks` does not divide
+/// `total_len`, the remainder is added to the last chunk.
+fn chunk_indices(num_chunks: u64, total_len: u64) -> impl Iterator<Item = (u64, u64)> {
+    let chunk_size = total_len / num_chunks;
+
+    (0..num_chunks).map(move |i| {
+        let start = i * chunk_size;
+        let end = if i == num_chunks - 1 {
+            total_len
+        } else {
+            start + chunk_size
+        };
+        (start, end)
+    })
+}
@@ -0,0 +1,52 @@
+//! Parsing files through a memory mapping, rather than copying them into buffers.
+
+use std::{fs::File, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{process_bytes, ChunkProcessingResult, Error, Options};
+
+/// Maps `file` into memory. Returns `None` if the file can't be mapped (e.g. if it's a
+/// pipe), in which case it should be read instead.
+pub(crate) fn map(file: &File, file_len: u64) -> Option<Mmap> {
+    // Some platforms refuse to map empty files, and there's nothing to gain anyway.
+    if file_len == 0 {
+        return None;
+    }
+
+    // SAFETY: Modifying the file while it's mapped is undefined behaviour, which we
+    // can't prevent, so it's documented as a requirement of `ReadMode::Mmap`.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+/// Returns the offset of the first line start in `bytes` at or after `offset`.
+pub(crate) fn next_line_start(bytes: &[u8], offset: u64) -> u64 {
+    let index = offset as usize;
+
+    if index == 0 || index >= bytes.len() || bytes[index - 1] == b'\n' {
+        return offset.min(bytes.len() as u64);
+    }
+
+    match bytes[index..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => (index + newline + 1) as u64,
+        None => bytes.len() as u64,
+    }
+}
+
+/// Parses measurements from `bytes[chunk_start..chunk_end]`, which must only contain
+/// whole lines.
+pub(crate) fn process_chunk(
+    bytes: &[u8],
+    file_path: &Path,
+    chunk_start: u64,
+    chunk_end: u64,
+    options: &Options,
+) -> Result<ChunkProcessingResult, Error> {
+    process_bytes(
+        &bytes[chunk_start as usize..chunk_end as usize],
+        Some(file_path),
+        chunk_start,
+        options.validation,
+        options.distribution,
+    )
+}
@@ -0,0 +1,147 @@
+//! Finding delimiters in the input several bytes at a time.
+//!
+//! Every function here gives the same result as a byte-at-a-time loop. The input is
+//! scanned a word (8 bytes) at a time, or, with the `simd` feature on x86_64 CPUs that
+//! support AVX2, [`find_byte`] scans 32 bytes at a time.
+
+use crate::table;
+
+/// Each byte of a word set to `0x01`.
+const LOW_BITS: u64 = 0x01_01_01_01_01_01_01_01;
+
+/// Each byte of a word set to `0x80`.
+const HIGH_BITS: u64 = 0x80_80_80_80_80_80_80_80;
+
+/// Returns the index of the first `needle` in `haystack`.
+#[inline]
+pub(crate) fn find_byte(haystack: &[u8], needle: u8) -> Option<usize> {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if haystack.len() >= avx2::BLOCK_LEN && std::is_x86_feature_detected!("avx2") {
+        // SAFETY: We just checked that the CPU supports AVX2.
+        return unsafe { avx2::find_byte(haystack, needle) };
+    }
+
+    find_byte_swar(haystack, needle)
+}
+
+/// Returns the index of the first `;` in `bytes`, which is the length of the station
+/// name at the start of `bytes`, along with the [`table::hash`] of that name.
+///
+/// This always scans a word at a time, even with the `simd` feature: station names are
+/// short, so hashing each word as it's scanned is faster than finding the `;` 32 bytes
+/// at a time and then hashing the name in a second pass.
+#[inline]
+pub(crate) fn find_station_end(bytes: &[u8]) -> Option<(usize, u64)> {
+    // Hash each word of the name as we scan it, so we don't need a second pass.
+    let mut hash = table::HASH_SEED;
+    let mut i = 0;
+
+    while let Some(word) = load_word(bytes, i) {
+        let matches = match_byte(word, b';');
+
+        if matches != 0 {
+            let len = first_match(matches);
+
+            if len > 0 {
+                // Zero the bytes from the `;` onwards, like the padding in `table::hash`.
+                hash = table::hash_word(hash, word & (u64::MAX >> (64 - len * 8)));
+            }
+
+            return Some((i + len, hash));
+        }
+
+        hash = table::hash_word(hash, word);
+        i += 8;
+    }
+
+    let len = find_byte_scalar(&bytes[i..], b';')?;
+
+    Some((i + len, table::hash_tail(hash, &bytes[i..i + len])))
+}
+
+/// Like [`find_byte`], but only ever scans a word at a time.
+fn find_byte_swar(haystack: &[u8], needle: u8) -> Option<usize> {
+    let mut i = 0;
+
+    while let Some(word) = load_word(haystack, i) {
+        let matches = match_byte(word, needle);
+
+        if matches != 0 {
+            return Some(i + first_match(matches));
+        }
+
+        i += 8;
+    }
+
+    find_byte_scalar(&haystack[i..], needle).map(|len| i + len)
+}
+
+/// Like [`find_byte`], but only ever scans a byte at a time, for the last few bytes of
+/// the input.
+fn find_byte_scalar(haystack: &[u8], needle: u8) -> Option<usize> {
+    haystack.iter().position(|&byte| byte == needle)
+}
+
+/// Loads `bytes[i..i + 8]` as a little-endian word, if it's in bounds.
+#[inline]
+fn load_word(bytes: &[u8], i: usize) -> Option<u64> {
+    let word = bytes.get(i..i + 8)?;
+
+    Some(u64::from_le_bytes(word.try_into().unwrap()))
+}
+
+/// Returns a word with the high bit set in each byte of `word` that is equal to
+/// `needle`. Bytes after the first match may also have their high bit set, so only the
+/// first match is meaningful.
+#[inline]
+fn match_byte(word: u64, needle: u8) -> u64 {
+    // The bytes equal to `needle` become zero, and subtracting one from a zero byte is
+    // the only way (before any borrow) to set its high bit when it wasn't already set.
+    let zeroed = word ^ (LOW_BITS * needle as u64);
+
+    zeroed.wrapping_sub(LOW_BITS) & !zeroed & HIGH_BITS
+}
+
+/// Returns the index of the first matching byte in a non-zero result of [`match_byte`].
+#[inline]
+fn first_match(matches: u64) -> usize {
+    matches.trailing_zeros() as usize / 8
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::{
+        __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8, _mm256_set1_epi8,
+    };
+
+    /// The number of bytes compared at once.
+    pub(super) const BLOCK_LEN: usize = 32;
+
+    /// Like [`super::find_byte`], but scans 32 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn find_byte(haystack: &[u8], needle: u8) -> Option<usize> {
+        let needles = _mm256_set1_epi8(needle as i8);
+
+        let mut i = 0;
+
+        while i + BLOCK_LEN <= haystack.len() {
+            // SAFETY: `haystack[i..i + BLOCK_LEN]` is in bounds, and the load doesn't
+            // need to be aligned.
+            let block = unsafe { _mm256_loadu_si256(haystack.as_ptr().add(i).cast::<__m256i>()) };
+
+            let matches = _mm256_movemask_epi8(_mm256_cmpeq_epi8(block, needles)) as u32;
+
+            if matches != 0 {
+                return Some(i + matches.trailing_zeros() as usize);
+            }
+
+            i += BLOCK_LEN;
+        }
+
+        super::find_byte_swar(&haystack[i..], needle).map(|len| i + len)
+    }
+}
@@ -0,0 +1,154 @@
+//! Summarising a station's measurements in a DDSketch, for approximate percentiles of
+//! measurements of any size.
+
+use crate::Percentile;
+
+/// The relative error of the percentiles of a [`Sketch`].
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// A [DDSketch](https://arxiv.org/abs/1908.10693) of a station's measurements, from
+/// which any percentile can be estimated to within 1% of its value.
+///
+/// Unlike a [`Histogram`](crate::Histogram), its size doesn't depend on the range of
+/// the measurements, but only grows with the logarithm of the ratio of the largest to
+/// the smallest measurement, and sketches merge without any loss of accuracy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sketch {
+    /// The positive measurements.
+    positive: Store,
+    /// The magnitudes of the negative measurements.
+    negative: Store,
+    /// The number of measurements that are zero.
+    zeros: u64,
+}
+
+impl Sketch {
+    /// Adds a measurement, in tenths. It's a float so that it can be outside the range
+    /// of `i32` tenths, but it must be finite.
+    #[inline]
+    pub(crate) fn record(&mut self, measurement: f64) {
+        if measurement > 0.0 {
+            self.positive.add(bin(measurement), 1);
+        } else if measurement < 0.0 {
+            self.negative.add(bin(measurement), 1);
+        } else {
+            self.zeros += 1;
+        }
+    }
+
+    /// Adds all of the measurements of `other`.
+    pub(crate) fn merge(&mut self, other: &Sketch) {
+        self.positive.merge(&other.positive);
+        self.negative.merge(&other.negative);
+        self.zeros += other.zeros;
+    }
+
+    /// The number of measurements.
+    pub fn count(&self) -> u64 {
+        self.positive.count() + self.negative.count() + self.zeros
+    }
+
+    /// An estimate of the measurement at `percentile`, in tenths (but not rounded), or
+    /// `None` if there are no measurements. It's within 1% of the nearest-rank
+    /// percentile, as described in [`Histogram::percentile`](crate::Histogram::percentile).
+    pub fn percentile(&self, percentile: Percentile) -> Option<f64> {
+        let rank = percentile.rank(self.count())?;
+
+        // Walk the measurements in ascending order: the negative ones from the largest
+        // magnitude down, then the zeros, then the positive ones.
+        let mut seen = 0;
+
+        for (index, count) in self.negative.bins().rev() {
+            seen += count;
+
+            if seen >= rank {
+                return Some(-value(index));
+            }
+        }
+
+        seen += self.zeros;
+
+        if seen >= rank {
+            return Some(0.0);
+        }
+
+        for (index, count) in self.positive.bins() {
+            seen += count;
+
+            if seen >= rank {
+                return Some(value(index));
+            }
+        }
+
+        unreachable!("the rank is at most the number of measurements")
+    }
+}
+
+/// `ln(gamma)`, where `gamma = (1 + a) / (1 - a)` for a relative accuracy `a`, is the
+/// ratio between the bounds of each bin.
+fn ln_gamma() -> f64 {
+    ((1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)).ln()
+}
+
+/// The bin of a non-zero measurement's magnitude, which holds `(gamma^(i - 1),
+/// gamma^i]`.
+#[inline]
+fn bin(measurement: f64) -> i32 {
+    (measurement.abs().ln() / ln_gamma()).ceil() as i32
+}
+
+/// The estimated magnitude of the measurements in bin `index`, which is within the
+/// relative accuracy of every magnitude in the bin.
+fn value(index: i32) -> f64 {
+    let gamma = ln_gamma().exp();
+
+    2.0 * gamma.powi(index) / (gamma + 1.0)
+}
+
+/// The counts of a range of consecutive bins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Store {
+    /// The index of the bin counted by `counts[0]`.
+    offset: i32,
+    counts: Vec<u64>,
+}
+
+impl Store {
+    fn add(&mut self, index: i32, count: u64) {
+        if self.counts.is_empty() {
+            self.offset = index;
+        } else if index < self.offset {
+            let extra = (self.offset - index) as usize;
+            self.counts.splice(0..0, std::iter::repeat(0).take(extra));
+            self.offset = index;
+        }
+
+        let i = (index - self.offset) as usize;
+
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+
+        self.counts[i] += count;
+    }
+
+    fn merge(&mut self, other: &Store) {
+        for (index, count) in other.bins() {
+            if count != 0 {
+                self.add(index, count);
+            }
+        }
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The index and count of each bin, in ascending order.
+    fn bins(&self) -> impl DoubleEndedIterator<Item = (i32, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (self.offset + i as i32, count))
+    }
+}
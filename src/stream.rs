@@ -0,0 +1,268 @@
+//! Parsing input that can't be split up in advance, such as a pipe, by reading it in
+//! blocks on one thread and parsing the blocks on worker threads.
+
+use std::{
+    io::Read,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    buffer::Buffer, count_lines, merge_chunk_results, process_bytes, ChunkProcessingResult, Error,
+    Options, Validation,
+};
+
+/// A block of whole lines read from the input, except that the last block may be
+/// missing its final newline.
+struct Block {
+    buffer: Buffer,
+    /// The number of bytes at the start of `buffer` that belong to this block.
+    len: usize,
+    /// The offset in the input of the start of the block.
+    offset: u64,
+    /// The number of lines before the block, only counted when validating, for errors.
+    lines: u64,
+}
+
+/// Aggregates the measurements read from `reader`. The current thread reads blocks of
+/// about `options.chunk_size` bytes, which are parsed by `options.threads` worker
+/// threads. `path` is the file being read, if any, for errors.
+pub(crate) fn aggregate(
+    mut reader: impl Read,
+    path: Option<&Path>,
+    options: &Options,
+) -> Result<ChunkProcessingResult, Error> {
+    let threads = options.threads.get();
+    let validation = options.validation;
+    let distribution = options.distribution;
+
+    // Bounding the channel bounds how far the reader can get ahead of the workers, and
+    // so how much memory we use.
+    let (block_sender, block_receiver) = mpsc::sync_channel::<Block>(threads);
+    // The receiver is dropped when every worker has stopped, so that the reader doesn't
+    // wait forever to send a block.
+    let block_receiver = Arc::new(Mutex::new(block_receiver));
+
+    // Workers send back the buffers of the blocks they've parsed, to be reused.
+    let (buffer_sender, buffer_receiver) = mpsc::channel::<Buffer>();
+
+    // Set when a worker fails, to stop reading early.
+    let failed = &AtomicBool::new(false);
+
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let block_receiver = block_receiver.clone();
+                let buffer_sender = buffer_sender.clone();
+
+                s.spawn(move || {
+                    let mut result = ChunkProcessingResult::new(distribution);
+
+                    loop {
+                        let Ok(block) = block_receiver.lock().unwrap().recv() else {
+                            break;
+                        };
+
+                        let bytes = &block.buffer.buffer()[..block.len];
+
+                        match process_bytes(bytes, path, block.offset, validation, distribution) {
+                            Ok(block_result) => result = merge_chunk_results(result, block_result),
+                            Err(err) => {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(with_lines_before(err, block.lines));
+                            }
+                        }
+
+                        // The reader may have finished already, in which case there's
+                        // nothing to reuse the buffer for.
+                        let _ = buffer_sender.send(block.buffer);
+                    }
+
+                    Ok(result)
+                })
+            })
+            .collect();
+
+        drop(block_receiver);
+        drop(buffer_sender);
+
+        let read_result = read_blocks(
+            &mut reader,
+            path,
+            options.chunk_size.get(),
+            validation,
+            &block_sender,
+            &buffer_receiver,
+            failed,
+        );
+
+        // Let the workers stop once they've parsed every block.
+        drop(block_sender);
+
+        let mut result = ChunkProcessingResult::new(distribution);
+        let mut first_error = None;
+
+        for handle in handles {
+            match handle.join().map_err(Error::thread_panic)? {
+                Ok(thread_result) => result = merge_chunk_results(result, thread_result),
+                Err(err) => first_error = Some(first_of(first_error, err)),
+            }
+        }
+
+        match (first_error, read_result) {
+            (Some(err), _) | (None, Err(err)) => Err(err),
+            (None, Ok(())) => Ok(result),
+        }
+    })
+}
+
+/// Reads `reader` into blocks of whole lines of about `block_size` bytes, and sends
+/// them to `blocks`, until the input ends or a worker has `failed`. Each block's buffer
+/// is reused from `buffers` if possible.
+fn read_blocks(
+    reader: &mut impl Read,
+    path: Option<&Path>,
+    block_size: usize,
+    validation: Validation,
+    blocks: &SyncSender<Block>,
+    buffers: &Receiver<Buffer>,
+    failed: &AtomicBool,
+) -> Result<(), Error> {
+    let mut buffer = Buffer::with_capacity(block_size);
+
+    // The offset in the input of the start of `buffer`.
+    let mut offset = 0;
+    // The number of lines before `buffer`, only counted when validating.
+    let mut lines = 0;
+
+    loop {
+        // Fill the buffer up to capacity, or with all remaining bytes of the input.
+        let mut end_of_input = false;
+
+        while buffer.buffer().len() < buffer.capacity() {
+            let read = buffer.read_more(&mut *reader).map_err(Error::io(
+                path.map(Path::to_path_buf),
+                Some(offset + buffer.buffer().len() as u64),
+            ))?;
+
+            if read == 0 {
+                end_of_input = true;
+                break;
+            }
+        }
+
+        let bytes = buffer.buffer();
+
+        let len = if end_of_input {
+            bytes.len()
+        } else {
+            match bytes.iter().rposition(|&byte| byte == b'\n') {
+                Some(newline) => newline + 1,
+                None => {
+                    // The line is longer than a whole block, so make room for more of it.
+                    let mut larger = Buffer::with_capacity(buffer.capacity() * 2);
+                    copy_into(&mut larger, bytes);
+                    buffer = larger;
+                    continue;
+                }
+            }
+        };
+
+        if len == 0 {
+            return Ok(());
+        }
+
+        // The start of the next block's first line is at the end of this block.
+        let tail = &bytes[len..];
+
+        let mut next = match buffers.try_recv() {
+            Ok(mut next) if next.capacity() > tail.len() => {
+                next.consume(next.capacity());
+                next.backshift();
+                next
+            }
+            // The tail may not fit in a buffer of `block_size` bytes if this buffer was
+            // made larger for a long line.
+            _ => Buffer::with_capacity(block_size.max(tail.len() * 2)),
+        };
+
+        copy_into(&mut next, tail);
+
+        let block_lines = match validation {
+            Validation::Fast => 0,
+            Validation::Strict | Validation::Skip => count_lines(&bytes[..len]),
+        };
+
+        let block = Block {
+            buffer,
+            len,
+            offset,
+            lines,
+        };
+
+        // If every worker has stopped, they'll report why.
+        if failed.load(Ordering::Relaxed) || blocks.send(block).is_err() {
+            return Ok(());
+        }
+
+        if end_of_input {
+            return Ok(());
+        }
+
+        buffer = next;
+        offset += len as u64;
+        lines += block_lines;
+    }
+}
+
+/// Appends `bytes` to `buffer`, which must have room for them.
+fn copy_into(buffer: &mut Buffer, bytes: &[u8]) {
+    let read = buffer
+        .read_more(bytes)
+        .expect("reading from a slice can't fail");
+
+    debug_assert_eq!(read, bytes.len());
+}
+
+/// Makes the line number of a malformed line, which is relative to its block, relative
+/// to the whole input, given the number of `lines` before the block.
+fn with_lines_before(err: Error, lines: u64) -> Error {
+    match err {
+        Error::MalformedLine { path, offset, line } => Error::MalformedLine {
+            path,
+            offset,
+            line: lines + line,
+        },
+        err => err,
+    }
+}
+
+/// Returns whichever error should be reported. Several workers may find a malformed
+/// line before they all stop, and only the first one in the input is reported.
+fn first_of(a: Option<Error>, b: Error) -> Error {
+    match (a, b) {
+        (
+            Some(
+                a @ Error::MalformedLine {
+                    offset: a_offset, ..
+                },
+            ),
+            b @ Error::MalformedLine {
+                offset: b_offset, ..
+            },
+        ) => {
+            if a_offset <= b_offset {
+                a
+            } else {
+                b
+            }
+        }
+        (Some(a), _) => a,
+        (None, b) => b,
+    }
+}
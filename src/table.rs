@@ -0,0 +1,207 @@
+//! A hash table of station names to [`Stats`], specialised for the parser's hot loop.
+
+use crate::{Distribution, Stats};
+
+/// The hash of an empty station name.
+pub(crate) const HASH_SEED: u64 = 0;
+
+/// The multiplier of the hash function, from FxHash.
+const HASH_MULTIPLIER: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// The number of slots a table starts with, once it has any stations.
+const INITIAL_SLOTS: usize = 1 << 10;
+
+/// Marks an empty slot.
+const EMPTY: u32 = u32::MAX;
+
+/// Adds the next 8 bytes of a station name, as a little-endian word, to `hash`. This
+/// lets the parser hash a name a word at a time while it scans for the `;` that ends it.
+#[inline]
+pub(crate) fn hash_word(hash: u64, word: u64) -> u64 {
+    (hash.rotate_left(5) ^ word).wrapping_mul(HASH_MULTIPLIER)
+}
+
+/// Adds the last (up to 8) bytes of a station name to `hash`, padded with zeros.
+#[inline]
+pub(crate) fn hash_tail(hash: u64, tail: &[u8]) -> u64 {
+    if tail.is_empty() {
+        return hash;
+    }
+
+    let mut word = [0; 8];
+    word[..tail.len()].copy_from_slice(tail);
+
+    hash_word(hash, u64::from_le_bytes(word))
+}
+
+/// Hashes a whole station name.
+pub(crate) fn hash(station: &[u8]) -> u64 {
+    let mut words = station.chunks_exact(8);
+
+    let hash = words.by_ref().fold(HASH_SEED, |hash, word| {
+        hash_word(hash, u64::from_le_bytes(word.try_into().unwrap()))
+    });
+
+    hash_tail(hash, words.remainder())
+}
+
+/// A station in a [`StationTable`].
+struct Entry {
+    /// The start of the station's name in [`StationTable::keys`].
+    key_start: usize,
+    /// The length of the station's name.
+    key_len: usize,
+    hash: u64,
+    stats: Stats,
+}
+
+/// An open-addressing hash table of station names to [`Stats`].
+///
+/// Station names are copied into a single arena rather than allocated individually,
+/// and collisions are resolved by linear probing. The table doubles in size whenever
+/// it's half full, so any number of stations is supported.
+#[derive(Default)]
+pub(crate) struct StationTable {
+    /// The names of all stations, back to back.
+    keys: Vec<u8>,
+    /// The stations, in insertion order.
+    entries: Vec<Entry>,
+    /// Indices into `entries`, or [`EMPTY`]. The length is always zero or a power of
+    /// two.
+    slots: Vec<u32>,
+    /// How new stations keep track of their measurements.
+    distribution: Distribution,
+}
+
+impl StationTable {
+    /// An empty table, whose stations keep track of their measurements according to
+    /// `distribution`.
+    pub(crate) fn new(distribution: Distribution) -> Self {
+        StationTable {
+            distribution,
+            ..StationTable::default()
+        }
+    }
+
+    /// How the table's stations keep track of their measurements.
+    pub(crate) fn distribution(&self) -> Distribution {
+        self.distribution
+    }
+
+    /// Returns the stats of `station`, whose hash is `hash`, inserting empty stats if
+    /// it isn't in the table yet.
+    #[inline]
+    pub(crate) fn get_or_insert(&mut self, hash: u64, station: &[u8]) -> &mut Stats {
+        let index = match self.find(hash, station) {
+            Ok(index) => index,
+            Err(slot) => self.insert(slot, hash, station, Stats::new(self.distribution)),
+        };
+
+        &mut self.entries[index].stats
+    }
+
+    /// Merges the stats of every station in `other` into this table. The stats of
+    /// stations that aren't in this table yet are moved rather than merged.
+    pub(crate) fn merge(&mut self, other: StationTable) {
+        let StationTable { keys, entries, .. } = other;
+
+        for entry in entries {
+            let station = &keys[entry.key_start..entry.key_start + entry.key_len];
+
+            match self.find(entry.hash, station) {
+                Ok(index) => self.entries[index].stats.merge(&entry.stats),
+                Err(slot) => {
+                    self.insert(slot, entry.hash, station, entry.stats);
+                }
+            }
+        }
+    }
+
+    /// Consumes the table, returning every station and its stats, in insertion order.
+    pub(crate) fn into_stations(self) -> impl Iterator<Item = (Vec<u8>, Stats)> {
+        let keys = self.keys;
+
+        self.entries.into_iter().map(move |entry| {
+            let station = keys[entry.key_start..entry.key_start + entry.key_len].to_vec();
+
+            (station, entry.stats)
+        })
+    }
+
+    /// Returns the index in `entries` of `station`, whose hash is `hash`, or the empty
+    /// slot to insert it into if it isn't in the table yet. Grows the table first if
+    /// it's half full, so the slot stays valid until the next insertion.
+    #[inline]
+    fn find(&mut self, hash: u64, station: &[u8]) -> Result<usize, usize> {
+        if self.entries.len() * 2 >= self.slots.len() {
+            self.grow();
+        }
+
+        let mask = self.slots.len() - 1;
+        let mut slot = slot_index(hash) & mask;
+
+        loop {
+            let index = self.slots[slot];
+
+            if index == EMPTY {
+                return Err(slot);
+            }
+
+            let entry = &self.entries[index as usize];
+
+            if entry.hash == hash
+                && &self.keys[entry.key_start..entry.key_start + entry.key_len] == station
+            {
+                return Ok(index as usize);
+            }
+
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    /// Inserts `station` with `stats` into the empty `slot` returned by
+    /// [`StationTable::find`], returning its index in `entries`.
+    fn insert(&mut self, slot: usize, hash: u64, station: &[u8], stats: Stats) -> usize {
+        let index = self.entries.len();
+        self.slots[slot] = index as u32;
+
+        self.entries.push(Entry {
+            key_start: self.keys.len(),
+            key_len: station.len(),
+            hash,
+            stats,
+        });
+        self.keys.extend_from_slice(station);
+
+        index
+    }
+
+    /// Doubles the number of slots, and re-inserts every station.
+    #[cold]
+    fn grow(&mut self) {
+        let len = (self.slots.len() * 2).max(INITIAL_SLOTS);
+
+        assert!(len <= EMPTY as usize, "too many stations");
+
+        self.slots.clear();
+        self.slots.resize(len, EMPTY);
+
+        let mask = len - 1;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let mut slot = slot_index(entry.hash) & mask;
+
+            while self.slots[slot] != EMPTY {
+                slot = (slot + 1) & mask;
+            }
+
+            self.slots[slot] = index as u32;
+        }
+    }
+}
+
+/// Mixes the high bits of `hash` into the low bits, which pick the slot.
+#[inline]
+fn slot_index(hash: u64) -> usize {
+    (hash ^ (hash >> 32)) as usize
+}
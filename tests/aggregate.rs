@@ -0,0 +1,1125 @@
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use flate2::write::GzEncoder;
+
+use challenge::{
+    aggregate_bytes, aggregate_file, aggregate_files, aggregate_reader, aggregate_stream,
+    format::{self, Columns},
+    Buckets, Distribution, Error, Options, Percentile, ReadMode, SkippedLines, StationStats,
+    Validation,
+};
+
+/// Writes `contents` to a file in the temp directory that is unique to this test.
+fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("challenge-{}-{name}", std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn to_reference(stats: &StationStats) -> String {
+    let mut out = Vec::new();
+    format::write_reference(&mut out, stats, &Columns::default()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Variations of `options` that change how files are split up and read.
+fn file_options(options: &Options) -> Vec<Options> {
+    let mut variations = Vec::new();
+
+    for threads in [1, 2, 7] {
+        // `3` makes chunks that are smaller than a line.
+        for chunk_size in [Options::DEFAULT_CHUNK_SIZE, 3.try_into().unwrap()] {
+            for read_mode in [ReadMode::Buffered, ReadMode::Mmap] {
+                variations.push(Options {
+                    threads: threads.try_into().unwrap(),
+                    chunk_size,
+                    read_mode,
+                    ..options.clone()
+                });
+            }
+        }
+    }
+
+    variations
+}
+
+/// Aggregates `contents` through every entry point of the library, with every validation
+/// mode, checking that they agree, and returns the result in the reference format.
+fn aggregate_all(name: &str, contents: &[u8]) -> String {
+    let path = temp_file(name, contents);
+
+    let expected = to_reference(&aggregate_bytes(contents, &Options::default()).unwrap());
+
+    for validation in [Validation::Fast, Validation::Strict, Validation::Skip] {
+        let options = Options {
+            validation,
+            ..Options::default()
+        };
+
+        let stats = aggregate_reader(contents, &options).unwrap();
+        assert_eq!(to_reference(&stats), expected, "{validation:?}");
+
+        for options in file_options(&options) {
+            let stats = aggregate_file(&path, &options).unwrap();
+            assert_eq!(to_reference(&stats), expected, "{options:?}");
+
+            let stats = aggregate_stream(contents, &options).unwrap();
+            assert_eq!(to_reference(&stats), expected, "stream {options:?}");
+        }
+    }
+
+    fs::remove_file(path).unwrap();
+
+    expected
+}
+
+/// Aggregates `contents` with strict validation through every entry point of the
+/// library, checking that they all report the same malformed line, and returns its
+/// `(offset, line)`.
+fn malformed_line(name: &str, contents: &[u8]) -> (u64, u64) {
+    let path = temp_file(name, contents);
+
+    let options = Options {
+        validation: Validation::Strict,
+        ..Options::default()
+    };
+
+    let location = |err| match err {
+        Error::MalformedLine { offset, line, .. } => (offset, line),
+        err => panic!("unexpected error: {err}"),
+    };
+
+    let expected = location(aggregate_bytes(contents, &options).unwrap_err());
+
+    assert_eq!(
+        location(aggregate_reader(contents, &options).unwrap_err()),
+        expected
+    );
+
+    for options in file_options(&options) {
+        let err = aggregate_file(&path, &options).unwrap_err();
+        assert_eq!(location(err), expected, "{options:?}");
+
+        let err = aggregate_stream(contents, &options).unwrap_err();
+        assert_eq!(location(err), expected, "stream {options:?}");
+    }
+
+    fs::remove_file(path).unwrap();
+
+    expected
+}
+
+#[test]
+fn empty_file() {
+    assert_eq!(aggregate_all("empty", b""), "{}");
+    assert!(aggregate_bytes(b"", &Options::default())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn blank_lines() {
+    assert_eq!(aggregate_all("blank", b"\n\n\n"), "{}");
+    assert_eq!(
+        aggregate_all("blank-between", b"\nHamburg;12.0\n\nHamburg;-3.4\n\n"),
+        "{Hamburg=-3.4/4.3/12.0}"
+    );
+}
+
+#[test]
+fn single_line() {
+    assert_eq!(
+        aggregate_all("single-line", b"Hamburg;12.0\n"),
+        "{Hamburg=12.0/12.0/12.0}"
+    );
+    assert_eq!(
+        aggregate_all("single-line-no-newline", b"Hamburg;12.0"),
+        "{Hamburg=12.0/12.0/12.0}"
+    );
+}
+
+#[test]
+fn single_station() {
+    let stats = aggregate_bytes(b"Abha;-0.1\nAbha;0.0\nAbha;5.5\n", &Options::default()).unwrap();
+
+    assert_eq!(stats.len(), 1);
+
+    let abha = stats.get(b"Abha").unwrap();
+    assert_eq!((abha.min, abha.sum, abha.count, abha.max), (-1, 54, 3, 55));
+
+    assert_eq!(
+        aggregate_all("single-station", b"Abha;-0.1\nAbha;0.0\nAbha;5.5\n"),
+        "{Abha=-0.1/1.8/5.5}"
+    );
+
+    // Means half-way between two tenths round towards positive infinity, and a mean
+    // that rounds to zero from below is never printed as `-0.0`.
+    assert_eq!(
+        aggregate_all("negative-zero-mean", b"a;-0.1\na;0.0\n"),
+        "{a=-0.1/0.0/0.0}"
+    );
+    assert_eq!(
+        aggregate_all("negative-half-mean", b"a;-0.2\na;-0.1\n"),
+        "{a=-0.2/-0.1/-0.1}"
+    );
+}
+
+#[test]
+fn many_stations() {
+    // Far more stations than the station table's initial capacity.
+    let contents: String = (0..5000)
+        .flat_map(|i| {
+            [
+                format!("s{i};{}.{}\n", i % 100, i % 10),
+                format!("s{i};-1.0\n"),
+            ]
+        })
+        .collect();
+
+    let stats = aggregate_bytes(contents.as_bytes(), &Options::default()).unwrap();
+    assert_eq!(stats.len(), 5000);
+
+    let s4321 = stats.get(b"s4321").unwrap();
+    assert_eq!(
+        (s4321.min, s4321.sum, s4321.count, s4321.max),
+        (-10, 201, 2, 211)
+    );
+
+    aggregate_all("many-stations", contents.as_bytes());
+}
+
+#[test]
+fn station_name_lengths() {
+    // Names that end at every position within a word, and within a 32-byte block.
+    let mut contents = String::new();
+    for len in 1..=100 {
+        contents += &format!("{};{}.5\n", "x".repeat(len), len % 100);
+    }
+
+    let stats = aggregate_bytes(contents.as_bytes(), &Options::default()).unwrap();
+    assert_eq!(stats.len(), 100);
+    assert_eq!(stats.get("x".repeat(37).as_bytes()).unwrap().max, 375);
+
+    aggregate_all("station-name-lengths", contents.as_bytes());
+}
+
+#[test]
+fn every_measurement() {
+    let mut contents = String::new();
+    for tenths in -999i32..=999 {
+        let sign = if tenths < 0 { "-" } else { "" };
+        contents += &format!(
+            "{tenths};{sign}{}.{}\n",
+            tenths.abs() / 10,
+            tenths.abs() % 10
+        );
+    }
+
+    let stats = aggregate_bytes(contents.as_bytes(), &Options::default()).unwrap();
+    assert_eq!(stats.len(), 1999);
+
+    for (station, stats) in &stats {
+        let tenths: i32 = std::str::from_utf8(station).unwrap().parse().unwrap();
+        assert_eq!((stats.min, stats.max), (tenths, tenths));
+    }
+
+    aggregate_all("every-measurement", contents.as_bytes());
+}
+
+#[test]
+fn strict_validation() {
+    let valid = "Abha;-0.1\nAbidjan;26.0\n\u{1F600};99.9\nAbha;-99.9\n".as_bytes();
+    assert_eq!(
+        aggregate_all("strict-valid", valid),
+        "{Abha=-99.9/-50.0/-0.1, Abidjan=26.0/26.0/26.0, \u{1F600}=99.9/99.9/99.9}"
+    );
+
+    assert_eq!(
+        malformed_line("no-separator", b"Abha;1.0\nAbidjan\n"),
+        (9, 2)
+    );
+    assert_eq!(
+        malformed_line("two-fractional", b"Abha;1.0\nAbha;1.23\n"),
+        (9, 2)
+    );
+    assert_eq!(
+        malformed_line("no-fractional", b"Abha;1.0\nAbha;1\n"),
+        (9, 2)
+    );
+    assert_eq!(
+        malformed_line("out-of-range", b"Abha;1.0\nAbha;100.0\n"),
+        (9, 2)
+    );
+    assert_eq!(
+        malformed_line("not-a-number", b"Abha;1.0\nAbha;abc\n"),
+        (9, 2)
+    );
+    assert_eq!(
+        malformed_line("empty-station", b"Abha;1.0\n\n;1.0"),
+        (10, 3)
+    );
+    assert_eq!(
+        malformed_line("invalid-utf8", b"Abha;1.0\n\xFF;1.0\n"),
+        (9, 2)
+    );
+
+    let long_station = [&[b'a'; 101][..], b";1.0\n"].concat();
+    assert_eq!(malformed_line("long-station", &long_station), (0, 1));
+
+    // The first malformed line is reported, even when a later chunk finds another.
+    let mut contents = b"Abha;1.0\n".repeat(100);
+    contents.extend_from_slice(b"Abha;1.\n");
+    contents.extend_from_slice(&b"Abha;1.0\n".repeat(100));
+    contents.extend_from_slice(b"Abha;.1\n");
+    assert_eq!(malformed_line("first", &contents), (900, 101));
+}
+
+#[test]
+fn skip_malformed_lines() {
+    let contents = b"a;1.0\nb;x\nc;2.0\n\nd\ne;3.0\n".repeat(3);
+
+    let path = temp_file("skip", &contents);
+
+    let options = Options {
+        validation: Validation::Skip,
+        ..Options::default()
+    };
+
+    let expected = SkippedLines {
+        count: 6,
+        sample_offsets: vec![6, 17, 31, 42, 56, 67],
+    };
+
+    let stats = aggregate_bytes(&contents, &options).unwrap();
+    assert_eq!(stats.skipped_lines(), &expected);
+    assert_eq!(
+        to_reference(&stats),
+        "{a=1.0/1.0/1.0, c=2.0/2.0/2.0, e=3.0/3.0/3.0}"
+    );
+
+    for options in file_options(&options) {
+        let stats = aggregate_file(&path, &options).unwrap();
+        assert_eq!(stats.skipped_lines(), &expected, "{options:?}");
+
+        let stats = aggregate_stream(&contents[..], &options).unwrap();
+        assert_eq!(stats.skipped_lines(), &expected, "stream {options:?}");
+    }
+
+    fs::remove_file(path).unwrap();
+
+    // Only the first few offsets are kept.
+    let stats = aggregate_bytes(&b"x\n".repeat(100), &options).unwrap();
+    assert_eq!(stats.skipped_lines().count, 100);
+    assert_eq!(
+        stats.skipped_lines().sample_offsets,
+        (0..SkippedLines::SAMPLE_SIZE as u64)
+            .map(|i| i * 2)
+            .collect::<Vec<_>>()
+    );
+
+    // A line longer than the reader's buffer doesn't end the input early.
+    let mut contents = b"a;1.0\n".to_vec();
+    contents.extend_from_slice(&[b'x'; 10_000]);
+    contents.push(b'\n');
+    contents.extend_from_slice(&b"b;2.0\n".repeat(1000));
+
+    let path = temp_file("skip-long-line", &contents);
+
+    let options = Options {
+        read_mode: ReadMode::Buffered,
+        ..options
+    };
+
+    let stats = aggregate_file(&path, &options).unwrap();
+    assert_eq!(stats.skipped_lines().count, 1);
+    assert_eq!(stats.get(b"b".as_slice()).unwrap().count, 1000);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn fast_malformed_lines() {
+    // The results are unspecified, but parsing mustn't panic.
+    let contents = b"a;1234\nb;12345678\nc;-1234\nd;1.0\ne;\nf;1234";
+
+    let path = temp_file("fast-malformed", contents);
+
+    aggregate_bytes(contents, &Options::default()).unwrap();
+
+    for options in file_options(&Options::default()) {
+        aggregate_file(&path, &options).unwrap();
+        aggregate_stream(&contents[..], &options).unwrap();
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn compressed_input() {
+    let contents = b"Hamburg;12.0\nAbha;-0.1\nHamburg;-3.4\nAbha;5.0".repeat(100);
+    let expected = to_reference(&aggregate_bytes(&contents, &Options::default()).unwrap());
+
+    let gzip = |contents: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(contents).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    // BGZF blocks, as written by `bgzip`, record their size in an extra field.
+    let bgzf = |contents: &[u8]| {
+        let mut encoder = flate2::GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), flate2::Compression::default());
+        encoder.write_all(contents).unwrap();
+
+        let mut block = encoder.finish().unwrap();
+        let size = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&size.to_le_bytes());
+        block
+    };
+
+    let zstd = |contents: &[u8], checksum: bool| {
+        let mut encoder = zstd::Encoder::new(Vec::new(), 0).unwrap();
+        encoder.include_checksum(checksum).unwrap();
+        encoder.write_all(contents).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    let (first, rest) = contents.split_at(1000);
+
+    // A skippable frame with 3 bytes of data.
+    let skippable = [
+        &0x184D_2A5F_u32.to_le_bytes()[..],
+        &3_u32.to_le_bytes(),
+        b"abc",
+    ]
+    .concat();
+
+    let inputs = [
+        ("gzip", gzip(&contents)),
+        ("gzip-members", [gzip(first), gzip(rest)].concat()),
+        ("bgzf", [bgzf(first), bgzf(rest), bgzf(b"")].concat()),
+        // Many blocks that split lines, which are only parsed right if they're
+        // decompressed back in order.
+        ("bgzf-blocks", contents.chunks(37).flat_map(bgzf).collect()),
+        ("zstd", zstd(&contents, false)),
+        (
+            "zstd-frames",
+            [zstd(first, true), skippable, zstd(rest, false)].concat(),
+        ),
+    ];
+
+    for (name, compressed) in inputs {
+        let path = temp_file(name, &compressed);
+
+        for options in file_options(&Options::default()) {
+            let stats = aggregate_file(&path, &options).unwrap();
+            assert_eq!(to_reference(&stats), expected, "{name} {options:?}");
+
+            let stats = aggregate_stream(&compressed[..], &options).unwrap();
+            assert_eq!(to_reference(&stats), expected, "stream {name} {options:?}");
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn multiple_files() {
+    let contents: [&[u8]; 3] = [
+        b"Hamburg;12.0\nAbha;-0.1\n",
+        b"",
+        b"Abha;5.0\nx\nHamburg;-3.4\nBulawayo;8.9",
+    ];
+
+    let paths: Vec<_> = contents
+        .iter()
+        .enumerate()
+        .map(|(i, contents)| temp_file(&format!("multiple-{i}"), contents))
+        .collect();
+
+    let options = Options {
+        validation: Validation::Skip,
+        ..Options::default()
+    };
+
+    for options in file_options(&options) {
+        let stats = aggregate_files(&paths, &options).unwrap();
+
+        assert_eq!(
+            to_reference(&stats.total),
+            "{Abha=-0.1/2.5/5.0, Bulawayo=8.9/8.9/8.9, Hamburg=-3.4/4.3/12.0}"
+        );
+        assert_eq!(stats.total.skipped_lines().count, 1);
+
+        assert_eq!(stats.files.len(), paths.len());
+
+        for ((path, file_stats), expected_path) in stats.files.iter().zip(&paths) {
+            assert_eq!(path, expected_path);
+            assert_eq!(
+                to_reference(file_stats),
+                to_reference(&aggregate_file(path, &options).unwrap()),
+                "{options:?}"
+            );
+        }
+
+        assert_eq!(stats.files[2].1.skipped_lines().sample_offsets, [9]);
+    }
+
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn json_output() {
+    let stats = aggregate_bytes(
+        b"a\"b\\c\x01;-0.1\nHamburg;12.0\nHamburg;-3.5\n\xFF;0.0\n",
+        &Options::default(),
+    )
+    .unwrap();
+
+    let mut out = Vec::new();
+    format::write_json(&mut out, &stats, &Columns::default()).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        concat!(
+            r#"[{"station":"Hamburg","min":-3.5,"mean":4.3,"max":12.0,"count":2,"sum":8.5},"#,
+            r#"{"station":"a\"b\\c\u0001","min":-0.1,"mean":-0.1,"max":-0.1,"count":1,"sum":-0.1},"#,
+            r#"{"station":"�","min":0.0,"mean":0.0,"max":0.0,"count":1,"sum":0.0}]"#,
+        )
+    );
+
+    let mut out = Vec::new();
+    format::write_json(&mut out, &StationStats::default(), &Columns::default()).unwrap();
+    assert_eq!(out, b"[]");
+}
+
+#[test]
+fn csv_output() {
+    let stats = aggregate_bytes(
+        b"a,b;1.0\nsay \"hi\";2.5\nHamburg;12.0\nHamburg;-3.5\nx\ty;0.0\n",
+        &Options::default(),
+    )
+    .unwrap();
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &Columns::default()).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station,min,mean,max,count\n\
+         Hamburg,-3.5,4.3,12.0,2\n\
+         \"a,b\",1.0,1.0,1.0,1\n\
+         \"say \"\"hi\"\"\",2.5,2.5,2.5,1\n\
+         x\ty,0.0,0.0,0.0,1\n"
+    );
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b'\t', &Columns::default()).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station\tmin\tmean\tmax\tcount\n\
+         Hamburg\t-3.5\t4.3\t12.0\t2\n\
+         a,b\t1.0\t1.0\t1.0\t1\n\
+         \"say \"\"hi\"\"\"\t2.5\t2.5\t2.5\t1\n\
+         \"x\ty\"\t0.0\t0.0\t0.0\t1\n"
+    );
+}
+
+#[test]
+fn totals() {
+    let stats = aggregate_bytes(
+        b"Hamburg;12.0\nAbha;-0.1\nHamburg;-3.5\n",
+        &Options::default(),
+    )
+    .unwrap();
+
+    let total = stats.total();
+    assert_eq!(
+        (total.min, total.sum, total.count, total.max),
+        (-35, 84, 3, 120)
+    );
+
+    let columns = Columns {
+        totals: true,
+        ..Columns::default()
+    };
+
+    let mut out = Vec::new();
+    format::write_reference(&mut out, &stats, &columns).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{Abha=-0.1/-0.1/-0.1/1/-0.1, Hamburg=-3.5/4.3/12.0/2/8.5} total=-3.5/2.8/12.0/3/8.4"
+    );
+
+    let mut out = Vec::new();
+    format::write_json(&mut out, &stats, &columns).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with(r#"{"station":null,"min":-3.5,"mean":2.8,"max":12.0,"count":3,"sum":8.4}]"#));
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &columns).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station,min,mean,max,count,sum\n\
+         Abha,-0.1,-0.1,-0.1,1,-0.1\n\
+         Hamburg,-3.5,4.3,12.0,2,8.5\n\
+         ,-3.5,2.8,12.0,3,8.4\n"
+    );
+
+    // There's nothing to total without any stations.
+    let mut out = Vec::new();
+    format::write_reference(&mut out, &StationStats::default(), &columns).unwrap();
+    assert_eq!(out, b"{}");
+}
+
+#[test]
+fn std_dev() {
+    let contents = b"Hamburg;1.0\nAbha;-0.1\nHamburg;2.0\nHamburg;3.0\nAbha;0.1\nHamburg;4.0\n";
+    let path = temp_file("std-dev", contents);
+
+    // Partial sums from chunks of every size are merged into the same variance.
+    for options in file_options(&Options::default()) {
+        let stats = aggregate_file(&path, &options).unwrap();
+
+        let hamburg = stats.get(b"Hamburg").unwrap();
+        assert_eq!(hamburg.sum_of_squares, 3000);
+        assert_eq!(hamburg.variance(), 1.25);
+
+        let abha = stats.get(b"Abha").unwrap();
+        assert_eq!(abha.std_dev(), 0.1);
+    }
+
+    fs::remove_file(path).unwrap();
+
+    let stats = aggregate_bytes(contents, &Options::default()).unwrap();
+
+    let columns = Columns {
+        std_dev: true,
+        ..Columns::default()
+    };
+
+    let mut out = Vec::new();
+    format::write_reference(&mut out, &stats, &columns).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{Abha=-0.1/0.0/0.1/0.10/0.01, Hamburg=1.0/2.5/4.0/1.12/1.25}"
+    );
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &columns).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station,min,mean,max,count,stddev,variance\n\
+         Abha,-0.1,0.0,0.1,2,0.10,0.01\n\
+         Hamburg,1.0,2.5,4.0,4,1.12,1.25\n"
+    );
+}
+
+#[test]
+fn percentiles() {
+    let percentiles: Vec<Percentile> = ["0", "50", "90", "99.9", "100"]
+        .iter()
+        .map(|percentile| percentile.parse().unwrap())
+        .collect();
+
+    // Enough measurements of `dense` that its histogram counts each bucket, and few
+    // enough of `sparse` that it keeps a list.
+    let mut dense = Vec::new();
+    let mut contents = String::new();
+
+    for i in 0..10_000 {
+        let tenths = (i * 7919) % 1999 - 999;
+        dense.push(tenths);
+        contents += &format!("dense;{:.1}\n", tenths as f64 / 10.0);
+    }
+    contents += "sparse;4.0\nsparse;1.0\nsparse;3.0\nsparse;2.0\n";
+
+    dense.sort_unstable();
+    // The percentiles are in thousandths of a percent, to keep this exact.
+    let nearest_rank = |percentile: usize| {
+        let rank = (percentile * dense.len()).div_ceil(100_000);
+        dense[rank.max(1) - 1]
+    };
+    let expected_dense: Vec<_> = [0, 50_000, 90_000, 99_900, 100_000]
+        .into_iter()
+        .map(nearest_rank)
+        .collect();
+
+    let path = temp_file("percentiles", contents.as_bytes());
+
+    let options = Options {
+        distribution: Distribution::Histogram,
+        ..Options::default()
+    };
+
+    // Histograms from chunks of every size are merged into the same percentiles.
+    for options in file_options(&options) {
+        let stats = aggregate_file(&path, &options).unwrap();
+
+        let percentiles_of = |station: &[u8]| {
+            let stats = stats.get(station).unwrap();
+            percentiles
+                .iter()
+                .map(|&percentile| stats.percentile(percentile).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(percentiles_of(b"dense"), expected_dense, "{options:?}");
+        assert_eq!(
+            percentiles_of(b"sparse"),
+            [10, 20, 40, 40, 40],
+            "{options:?}"
+        );
+    }
+
+    // The total of several files keeps the histograms of each station.
+    let stats = aggregate_files(&[&path, &path], &options).unwrap();
+    let dense = stats.total.get(b"dense".as_slice()).unwrap();
+    assert_eq!(dense.histogram().unwrap().count(), 20_000);
+    assert_eq!(
+        percentiles
+            .iter()
+            .map(|&percentile| dense.percentile(percentile).unwrap())
+            .collect::<Vec<_>>(),
+        expected_dense
+    );
+
+    fs::remove_file(path).unwrap();
+
+    let stats = aggregate_bytes(b"b;1.0\nb;2.0\na;-5.0\n", &options).unwrap();
+
+    // So does the total of every station.
+    assert_eq!(stats.total().percentile("50".parse().unwrap()), Some(10));
+
+    let columns = Columns {
+        percentiles: vec!["50".parse().unwrap(), "99.9".parse().unwrap()],
+        ..Columns::default()
+    };
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &columns).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station,min,mean,max,count,p50,p99.9\n\
+         a,-5.0,-5.0,-5.0,1,-5.0,-5.0\n\
+         b,1.0,1.5,2.0,2,1.0,2.0\n"
+    );
+
+    // Without histograms, there are no percentiles to write.
+    let stats = aggregate_bytes(b"a;1.0\n", &Options::default()).unwrap();
+
+    let mut out = Vec::new();
+    format::write_json(&mut out, &stats, &columns).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with(r#""p50":null,"p99.9":null}]"#));
+
+    for invalid in ["", "-1", "100.1", "1e2", "50.", ".5", "12.34567", "1000"] {
+        assert!(invalid.parse::<Percentile>().is_err(), "{invalid:?}");
+    }
+
+    assert_eq!(
+        "050.2500".parse::<Percentile>().unwrap().to_string(),
+        "50.25"
+    );
+}
+
+#[test]
+fn sketch_percentiles() {
+    let percentiles: Vec<Percentile> = ["0", "1", "25", "50", "75", "99", "100"]
+        .iter()
+        .map(|percentile| percentile.parse().unwrap())
+        .collect();
+
+    // Negative, zero and positive measurements, spread over the whole range.
+    let mut measurements = Vec::new();
+    let mut contents = String::new();
+
+    for i in 0..5_000 {
+        let tenths = (i * 7919) % 1999 - 999;
+        let tenths = if i % 10 == 0 { 0 } else { tenths };
+        measurements.push(tenths);
+        contents += &format!("a;{:.1}\n", tenths as f64 / 10.0);
+    }
+
+    measurements.sort_unstable();
+    let exact: Vec<i32> = [0, 1, 25, 50, 75, 99, 100]
+        .into_iter()
+        .map(|percentile: usize| {
+            let rank = (percentile * measurements.len()).div_ceil(100);
+            measurements[rank.max(1) - 1]
+        })
+        .collect();
+
+    let path = temp_file("sketch", contents.as_bytes());
+
+    let options = Options {
+        distribution: Distribution::Sketch,
+        ..Options::default()
+    };
+
+    let mut sketches = Vec::new();
+
+    for options in file_options(&options) {
+        let stats = aggregate_file(&path, &options).unwrap();
+        let stats = stats.get(b"a".as_slice()).unwrap();
+
+        assert!(stats.histogram().is_none());
+        sketches.push(stats.sketch().unwrap().clone());
+
+        for (&percentile, &exact) in percentiles.iter().zip(&exact) {
+            let estimate = stats.percentile(percentile).unwrap();
+
+            // Within 1%, and half a tenth for rounding.
+            assert!(
+                (estimate - exact).abs() <= exact.abs() / 100 + 1,
+                "p{percentile}: {estimate} vs {exact}, {options:?}"
+            );
+        }
+    }
+
+    // Sketches of chunks of every size merge into the same sketch.
+    assert!(sketches.windows(2).all(|pair| pair[0] == pair[1]));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn sketch_wide_measurements() {
+    let contents = b"a;1234.5\na;-99999.9\na;0.1\nb;123456789012345678901234.5\nb;-0.0\nc;1.23\n";
+    let p0 = "0".parse().unwrap();
+    let p100 = "100".parse().unwrap();
+
+    // Without a sketch, measurements outside the challenge's range are malformed.
+    let strict = Options {
+        validation: Validation::Strict,
+        ..Options::default()
+    };
+    assert!(matches!(
+        aggregate_bytes(contents, &strict),
+        Err(Error::MalformedLine { line: 1, .. })
+    ));
+
+    let path = temp_file("sketch-wide", contents);
+
+    for validation in [Validation::Fast, Validation::Skip] {
+        let options = Options {
+            validation,
+            distribution: Distribution::Sketch,
+            ..Options::default()
+        };
+
+        for options in file_options(&options) {
+            let stats = aggregate_file(&path, &options).unwrap();
+
+            let a = stats.get(b"a".as_slice()).unwrap();
+            assert_eq!((a.min, a.sum, a.count, a.max), (-999999, -987653, 3, 12345));
+            assert_eq!(a.sketch().unwrap().count(), 3);
+
+            let (min, max) = (a.percentile(p0).unwrap(), a.percentile(p100).unwrap());
+            assert!((min + 999999).abs() <= 10000, "{min}");
+            assert!((max - 12345).abs() <= 124, "{max}");
+
+            // The sketch keeps measurements that don't fit in `i32` tenths.
+            let b = stats.get(b"b".as_slice()).unwrap();
+            assert_eq!((b.max, b.count), (i32::MAX, 2));
+
+            let max = b.sketch().unwrap().percentile(p100).unwrap();
+            assert!(
+                (max / 1234567890123456789012345.0 - 1.0).abs() <= 0.01,
+                "{max}"
+            );
+
+            // Measurements still need exactly one fractional digit.
+            assert!(stats.get(b"c".as_slice()).is_none());
+
+            if validation == Validation::Skip {
+                assert_eq!(stats.skipped_lines().count, 1);
+            }
+        }
+    }
+
+    let options = Options {
+        distribution: Distribution::Sketch,
+        ..strict
+    };
+    assert!(matches!(
+        aggregate_file(&path, &options),
+        Err(Error::MalformedLine { line: 6, .. })
+    ));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn histogram_buckets() {
+    let options = Options {
+        distribution: Distribution::Histogram,
+        ..Options::default()
+    };
+
+    // Enough measurements that the histogram counts each bucket.
+    let mut contents = String::new();
+
+    for i in 0..10_000 {
+        contents += &format!("a;{:.1}\n", (i % 200 - 100) as f64 / 10.0);
+    }
+
+    let path = temp_file("histogram_buckets", contents.as_bytes());
+
+    // Histograms from chunks of every size are merged into the same counts.
+    for options in file_options(&options) {
+        let stats = aggregate_file(&path, &options).unwrap();
+        let histogram = stats.get(b"a".as_slice()).unwrap().histogram().unwrap();
+
+        assert_eq!(
+            histogram.bucket_counts(&[-100, 0, 50, 99]),
+            [5_000, 2_500, 2_500],
+            "{options:?}"
+        );
+    }
+
+    fs::remove_file(path).unwrap();
+
+    let stats = aggregate_bytes(b"a;-0.1\na;0.0\na;4.9\nb;5.0\nb;12.0\n", &options).unwrap();
+
+    let width = Columns {
+        histogram: Some(Buckets::Width(50.try_into().unwrap())),
+        ..Columns::default()
+    };
+
+    let mut out = Vec::new();
+    format::write_json(&mut out, &stats, &width).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        concat!(
+            r#"[{"station":"a","min":-0.1,"mean":1.6,"max":4.9,"count":3,"sum":4.8,"#,
+            r#""histogram":{"edges":[-5.0,0.0,5.0],"counts":[1,2]}},"#,
+            r#"{"station":"b","min":5.0,"mean":8.5,"max":12.0,"count":2,"sum":17.0,"#,
+            r#""histogram":{"edges":[5.0,10.0,15.0],"counts":[1,1]}}]"#,
+        )
+    );
+
+    // CSV buckets cover every station, and the last edge is included in the last bucket.
+    let edges = Columns {
+        histogram: Some(Buckets::Edges(vec![0, 50, 120])),
+        ..Columns::default()
+    };
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &width).unwrap();
+    format::write_csv(&mut out, &stats, b',', &edges).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "station,min,mean,max,count,-5.0..0.0,0.0..5.0,5.0..10.0,10.0..15.0\n\
+         a,-0.1,1.6,4.9,3,1,2,0,0\n\
+         b,5.0,8.5,12.0,2,0,0,1,1\n\
+         station,min,mean,max,count,0.0..5.0,5.0..12.0\n\
+         a,-0.1,1.6,4.9,3,2,0\n\
+         b,5.0,8.5,12.0,2,0,2\n"
+    );
+
+    // Without histograms, there are no counts to write.
+    let stats = aggregate_bytes(b"a;1.0\n", &Options::default()).unwrap();
+
+    let mut out = Vec::new();
+    format::write_csv(&mut out, &stats, b',', &edges).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with("a,1.0,1.0,1.0,1,,\n"));
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_output() {
+    use arrow_array::{cast::AsArray, types::Float64Type, RecordBatch};
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let stats = aggregate_bytes(
+        b"Hamburg;12.0\nAbha;-0.1\nHamburg;-3.5\n",
+        &Options::default(),
+    )
+    .unwrap();
+
+    let columns = Columns {
+        totals: true,
+        ..Columns::default()
+    };
+
+    let check = |batch: &RecordBatch| {
+        let station = batch.column_by_name("station").unwrap().as_string::<i32>();
+        assert_eq!(
+            station.iter().collect::<Vec<_>>(),
+            [Some("Abha"), Some("Hamburg"), None]
+        );
+
+        let column = |name| {
+            let column = batch.column_by_name(name).unwrap();
+            column.as_primitive::<Float64Type>().values().to_vec()
+        };
+
+        assert_eq!(column("min"), [-0.1, -3.5, -3.5]);
+        assert_eq!(column("max"), [-0.1, 12.0, 12.0]);
+        assert_eq!(column("mean"), [-0.1, 4.25, 2.8]);
+        assert_eq!(column("sum"), [-0.1, 8.5, 8.4]);
+    };
+
+    let mut out = Vec::new();
+    format::write_arrow(&mut out, &stats, &columns).unwrap();
+
+    let batches = FileReader::try_new(std::io::Cursor::new(out), None)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(batches.len(), 1);
+    check(&batches[0]);
+
+    let mut out = Vec::new();
+    format::write_parquet(&mut out, &stats, &columns).unwrap();
+
+    let path = temp_file("parquet", &out);
+    let batches = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(batches.len(), 1);
+    check(&batches[0]);
+}
+
+#[test]
+fn binary_prints_empty_object_for_empty_file() {
+    let path = temp_file("binary-empty", b"");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_challenge"))
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    fs::remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"{}");
+}
+
+#[test]
+fn binary_reads_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_challenge"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"Hamburg;12.0\nAbha;-0.1\nHamburg;-3.4")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        b"{Abha=-0.1/-0.1/-0.1, Hamburg=-3.4/4.3/12.0}"
+    );
+}
+
+#[test]
+fn binary_reads_piped_stdin_without_inputs() {
+    // An empty directory, so there's no `measurements.txt` to read instead.
+    let dir = std::env::temp_dir().join(format!("challenge-{}-no-inputs", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_challenge"))
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(b"a;1.0\n").unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    fs::remove_dir(dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"{a=1.0/1.0/1.0}");
+}
+
+#[cfg(unix)]
+#[test]
+fn binary_reads_more_files_than_it_can_open_at_once() {
+    let dir = std::env::temp_dir().join(format!("challenge-{}-many-files", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    for i in 0..100 {
+        fs::write(dir.join(format!("{i}.txt")), b"a;1.0\n").unwrap();
+    }
+
+    for args in [&[][..], &["--mmap"]] {
+        // Far fewer files can be open at once than there are files.
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(r#"ulimit -n 32 && exec "$0" "$@""#)
+            .arg(env!("CARGO_BIN_EXE_challenge"))
+            .args(args)
+            .arg(&dir)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{args:?} {output:?}");
+        assert_eq!(output.stdout, b"{a=1.0/1.0/1.0}", "{args:?}");
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn binary_reports_missing_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_challenge"))
+        .arg("this-file-does-not-exist.txt")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("this-file-does-not-exist.txt"));
+}
+
+#[test]
+fn binary_reports_empty_directory() {
+    let dir = std::env::temp_dir().join(format!("challenge-{}-empty-dir", std::process::id()));
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join(".hidden"), "a;1.0\n").unwrap();
+
+    // Neither a directory nor a glob that only has empty directories has any files.
+    for input in [dir.clone(), dir.join("nest*")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_challenge"))
+            .arg(&input)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(3), "{input:?}");
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(dir.to_str().unwrap()));
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}